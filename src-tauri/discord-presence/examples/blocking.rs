@@ -1,14 +1,24 @@
-use discord_presence::{Client, Event};
+use std::sync::mpsc;
 
 fn main() {
     tracing_subscriber::fmt()
         .with_max_level(tracing::Level::TRACE)
         .init();
 
-    let mut drpc = Client::new(1003450375732482138);
+    // With the `testing` feature the example talks to a fake Discord instead
+    #[cfg(all(unix, feature = "testing"))]
+    let server =
+        discord_presence::testing::MockServer::start().expect("Failed to start the mock server");
+    #[cfg(all(unix, feature = "testing"))]
+    let mut drpc = server.client_builder(1003450375732482138).build();
+    #[cfg(not(all(unix, feature = "testing")))]
+    let mut drpc = discord_presence::Client::new(1003450375732482138);
 
-    drpc.on_ready(|_ctx| {
+    // Registered before starting, a handler added afterwards could miss the event
+    let (ready, on_ready) = mpsc::channel();
+    drpc.on_ready(move |_ctx| {
         println!("ready?");
+        ready.send(()).ok();
     });
 
     drpc.on_activity_join_request(|ctx| {
@@ -23,22 +33,35 @@ fn main() {
         println!("Spectate: {:?}", ctx.event);
     });
 
-    let drpc_thread = drpc.start();
+    drpc.start();
 
-    drpc.block_until_event(Event::Ready).unwrap();
+    on_ready.recv().unwrap();
 
-    assert!(Client::is_ready());
+    assert!(drpc.is_ready());
 
     // Set the activity
-    drpc.set_activity(|act| act.state("rusting frfr"))
+    drpc.set_activity(|act| act.state(Some("rusting frfr".to_owned())))
         .expect("Failed to set activity");
 
+    // The fake Discord has the user join, then the example exits instead of waiting for Ctrl-C
+    #[cfg(all(unix, feature = "testing"))]
+    {
+        use discord_presence::Event;
+
+        let mut joined = drpc.clone();
+        drpc.once(Event::ActivityJoin, move |_ctx| joined.shutdown());
+        server
+            .dispatch(Event::ActivityJoin, serde_json::json!({ "secret": "mock" }))
+            .unwrap();
+    }
+
+    let mut handle = drpc.clone();
     ctrlc::set_handler(move || {
         println!("Exiting...");
-        drpc.clear_activity().unwrap();
-        std::process::exit(0);
+        handle.clear_activity().unwrap();
+        handle.shutdown();
     })
     .unwrap();
 
-    drpc_thread.join().unwrap();
+    drpc.block_until_shutdown();
 }
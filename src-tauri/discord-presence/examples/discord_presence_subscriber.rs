@@ -1,32 +1,70 @@
-use discord_presence::Client;
+use std::sync::mpsc;
 
 fn main() {
     tracing_subscriber::fmt()
         .with_max_level(tracing::Level::TRACE)
         .init();
 
-    let mut drpc = Client::new(1003450375732482138);
+    // With the `testing` feature the example talks to a fake Discord instead
+    #[cfg(all(unix, feature = "testing"))]
+    let server =
+        discord_presence::testing::MockServer::start().expect("Failed to start the mock server");
+    #[cfg(all(unix, feature = "testing"))]
+    let mut drpc = server.client_builder(1003450375732482138).build();
+    #[cfg(not(all(unix, feature = "testing")))]
+    let mut drpc = discord_presence::Client::new(1003450375732482138);
 
-    drpc.on_ready(|_ctx| {
+    let (ready, on_ready) = mpsc::channel();
+    drpc.on_ready(move |_ctx| {
         println!("READY!");
+        ready.send(()).ok();
     });
 
     drpc.on_error(|ctx| {
-        eprintln!("An error occured, {}", ctx.event);
+        eprintln!("An error occured, {}", ctx.raw);
     });
 
-    let drpc_thread = drpc.start();
+    // Answer join requests on the main thread, the handler only passes them on
+    let (requests, incoming) = mpsc::channel();
+    drpc.on_activity_join_request(move |ctx| {
+        if let Some(user_id) = ctx.event.user_id() {
+            requests.send(user_id).ok();
+        }
+    });
+
+    drpc.start();
+    on_ready.recv().unwrap();
 
     if let Err(why) = drpc.set_activity(|a| {
-        a.state("Running examples").assets(|ass| {
-            ass.large_image("ferris_wat")
-                .large_text("wat.")
-                .small_image("rusting")
-                .small_text("rusting...")
-        })
+        a.state(Some("Running examples".to_owned()))
+            .party(|p| p.id(Some("examples".to_owned())).size(Some((1, 4))))
+            .secrets(|s| s.join(Some("join-the-examples".to_owned())))
+            .assets(|ass| {
+                ass.large_image(Some("ferris_wat".to_owned()))
+                    .large_text(Some("wat.".to_owned()))
+                    .small_image(Some("rusting".to_owned()))
+                    .small_text(Some("rusting...".to_owned()))
+            })
     }) {
         println!("Failed to set presence: {}", why);
     }
 
-    drpc_thread.join().unwrap()
+    // The fake Discord sends a single join request, then the example exits
+    #[cfg(all(unix, feature = "testing"))]
+    let incoming = {
+        server
+            .dispatch(
+                discord_presence::Event::ActivityJoinRequest,
+                serde_json::json!({ "user": { "id": "53908232506183680", "username": "Mason" } }),
+            )
+            .unwrap();
+        incoming.into_iter().take(1)
+    };
+
+    for user_id in incoming {
+        println!("{} asked to join, accepting", user_id);
+        if let Err(why) = drpc.accept_join_request(user_id) {
+            println!("Failed to accept join request: {}", why);
+        }
+    }
 }
@@ -0,0 +1,37 @@
+use discord_presence::{testing::MockServer, Event};
+use serde_json::json;
+use std::time::Duration;
+
+fn main() {
+    tracing_subscriber::fmt()
+        .with_max_level(tracing::Level::TRACE)
+        .init();
+
+    let server = MockServer::start().expect("Failed to start the mock server");
+    let mut drpc = server.client_builder(1003450375732482138).build();
+
+    drpc.on_ready(|ctx| {
+        let user = ctx.event.user.unwrap_or_default();
+        println!("Ready as {}", user.username.unwrap_or_default());
+    });
+
+    drpc.on_activity_join(|ctx| {
+        println!("Joined: {:?}", ctx.event);
+    });
+
+    drpc.start();
+    while !drpc.is_ready() {
+        std::thread::sleep(Duration::from_millis(10));
+    }
+
+    drpc.set_activity(|act| act.state(Some("Testing without Discord".to_owned())))
+        .expect("Failed to set activity");
+    println!("Discord received: {}", server.activities()[0]);
+
+    server
+        .dispatch(Event::ActivityJoin, json!({ "secret": "mock" }))
+        .unwrap();
+    std::thread::sleep(Duration::from_millis(100));
+
+    drpc.shutdown();
+}
@@ -1,19 +1,31 @@
-use discord_presence::Client;
+use std::time::Duration;
 
 fn main() {
     tracing_subscriber::fmt()
         .with_max_level(tracing::Level::TRACE)
         .init();
 
-    let mut client = Client::new(1003450375732482138);
+    // With the `testing` feature the example talks to a fake Discord instead
+    #[cfg(all(unix, feature = "testing"))]
+    let server =
+        discord_presence::testing::MockServer::start().expect("Failed to start the mock server");
+    #[cfg(all(unix, feature = "testing"))]
+    let mut client = server.client_builder(1003450375732482138).build();
+    #[cfg(not(all(unix, feature = "testing")))]
+    let mut client = discord_presence::Client::new(1003450375732482138);
 
-    _ = client.start();
+    client.start();
+
+    // `start` returns right away, commands fail until the handshake is done
+    while !client.is_ready() {
+        std::thread::sleep(Duration::from_millis(10));
+    }
 
     client
         .set_activity(|a| {
-            a.state("Rust")
-                .details("Programming")
-                .assets(|a| a.large_image("rust"))
+            a.state(Some("Rust".to_owned()))
+                .details(Some("Programming".to_owned()))
+                .assets(|a| a.large_image(Some("rust".to_owned())))
         })
         .unwrap();
 
@@ -0,0 +1,470 @@
+use crate::{
+    connection::{AsyncManager, Config, ConnectionInfo},
+    event_handler::{Context as EventContext, HandlerId, HandlerRegistry, RemoveOnDrop},
+    models::{
+        message::Message,
+        payload::Payload,
+        rich_presence::{
+            Activity, ActivityJoinEvent, ActivityJoinRequestEvent, ActivitySpectateEvent,
+            SetActivityArgs,
+        },
+        Command, ErrorEvent, Event, OpCode, ReadyEvent, Subscription, SubscriptionArgs,
+        ValidationMode,
+    },
+    ClientBuilder, DiscordError, Result,
+};
+use serde::{de::DeserializeOwned, Serialize};
+use std::{future::Future, sync::Arc};
+use tokio::sync::oneshot;
+
+/// Stops reading from Discord and drops the socket once the last clone of the client is gone
+struct AbortOnDrop(AsyncManager);
+
+impl Drop for AbortOnDrop {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+/// The Discord client, driven by the tokio runtime instead of a dedicated thread
+///
+/// ```no_run
+/// # async fn run() -> discord_presence::Result<()> {
+/// use discord_presence::AsyncClient;
+///
+/// let mut client = AsyncClient::new(1003450375732482138);
+/// client.connect().await?;
+/// client
+///     .set_activity(|a| a.state(Some("Async".to_owned())))
+///     .await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct AsyncClient {
+    connection_manager: AsyncManager,
+    event_handler_registry: HandlerRegistry,
+    activity_validation: ValidationMode,
+    _abort: Arc<AbortOnDrop>,
+}
+
+impl AsyncClient {
+    /// Creates a new `AsyncClient`
+    pub fn new(client_id: u64) -> Self {
+        Self::with_config(client_id, Config::default())
+    }
+
+    /// Creates a [`ClientBuilder`] to customize the connection
+    pub fn builder(client_id: u64) -> ClientBuilder {
+        ClientBuilder::new(client_id)
+    }
+
+    pub(crate) fn with_config(client_id: u64, config: Config) -> Self {
+        let event_handler_registry = HandlerRegistry::new(config.handler_executor.clone());
+        let activity_validation = config.activity_validation;
+        let connection_manager =
+            AsyncManager::new(client_id, config, event_handler_registry.clone());
+        Self {
+            _abort: Arc::new(AbortOnDrop(connection_manager.clone())),
+            connection_manager,
+            event_handler_registry,
+            activity_validation,
+        }
+    }
+
+    /// Connect to Discord and complete the handshake
+    ///
+    /// Resolves once the `Ready` event has been handled. Incoming events are read on a
+    /// spawned task, so this must be called from within a tokio runtime.
+    /// Fails with [`DiscordError::Closed`] if Discord rejects the handshake.
+    pub async fn connect(&mut self) -> Result<()> {
+        self.connection_manager.connect().await
+    }
+
+    /// Close the connection to Discord
+    ///
+    /// Sends Discord a `Close` frame first. Dropping the last clone of the client also closes
+    /// the connection, but without the `Close` frame.
+    pub async fn disconnect(&mut self) {
+        self.connection_manager.disconnect().await
+    }
+
+    /// The application ID this client was created with
+    pub fn client_id(&self) -> u64 {
+        self.connection_manager.get_client_id()
+    }
+
+    /// The socket index and Discord build the client is connected to
+    pub fn connection_info(&self) -> Option<ConnectionInfo> {
+        self.connection_manager.connection_info()
+    }
+
+    /// Check if the client is connected and ready
+    pub fn is_ready(&self) -> bool {
+        self.connection_manager.is_ready()
+    }
+
+    async fn execute<A, E>(&self, cmd: Command, args: A, evt: Option<Event>) -> Result<Payload<E>>
+    where
+        A: Serialize + Send + Sync,
+        E: Serialize + DeserializeOwned,
+    {
+        if let Some(why) = self.connection_manager.take_close_error() {
+            return Err(why);
+        }
+
+        if !self.is_ready() {
+            return Err(DiscordError::NotStarted);
+        }
+
+        trace!("Executing command: {:?}", cmd);
+        let payload = Payload::with_nonce(cmd, Some(args), None, evt);
+        let nonce = into_error!(payload.nonce.clone())?;
+        let message = Message::new(OpCode::Frame, payload)?;
+
+        let response = self.connection_manager.execute(nonce, message).await?;
+
+        Ok(serde_json::from_value(response)?)
+    }
+
+    /// Set the users current activity
+    ///
+    /// Returns the activity as Discord applied it.
+    /// Fails with [`DiscordError::InvalidActivity`] if the activity breaks Discord's limits
+    /// and [`ValidationMode::Reject`] is set.
+    pub async fn set_activity<F>(&self, f: F) -> Result<Payload<Activity>>
+    where
+        F: FnOnce(Activity) -> Activity,
+    {
+        let activity = self.activity_validation.apply(f(Activity::new()))?;
+        self.execute(
+            Command::SetActivity,
+            SetActivityArgs::new(|_| activity),
+            None,
+        )
+        .await
+    }
+
+    /// Clear the users current activity
+    pub async fn clear_activity(&self) -> Result<Payload<Activity>> {
+        self.execute(Command::SetActivity, SetActivityArgs::default(), None)
+            .await
+    }
+
+    /// Subscribe to an event, renewing the subscription whenever the client connects
+    ///
+    /// Registering a handler for an event that needs a subscription does this automatically.
+    pub async fn subscribe<F>(&self, event: Event, f: F) -> Result<Payload<Subscription>>
+    where
+        F: FnOnce(SubscriptionArgs) -> SubscriptionArgs,
+    {
+        let args = f(SubscriptionArgs::new());
+        let response = self.execute(Command::Subscribe, &args, Some(event)).await?;
+        self.connection_manager.keep_subscription(event, args);
+
+        Ok(response)
+    }
+
+    /// Unsubscribe from an event
+    ///
+    /// Handlers registered for the event stay registered but are no longer called.
+    pub async fn unsubscribe<F>(&self, event: Event, f: F) -> Result<Payload<Subscription>>
+    where
+        F: FnOnce(SubscriptionArgs) -> SubscriptionArgs,
+    {
+        self.connection_manager.forget_subscription(event);
+        self.execute(
+            Command::Unsubscribe,
+            f(SubscriptionArgs::new()),
+            Some(event),
+        )
+        .await
+    }
+
+    /// Register a handler for a given event
+    ///
+    /// Handlers run on the [`HandlerExecutor`](crate::HandlerExecutor) set with
+    /// [`ClientBuilder::handler_executor`], by default a dedicated thread. Events Discord
+    /// only sends to subscribers, like [`Event::ActivityJoin`], are subscribed to as well.
+    pub fn on_event<F>(&mut self, event: Event, handler: F) -> HandlerId
+    where
+        F: Fn(EventContext) + 'static + Send + Sync,
+    {
+        let id = self.event_handler_registry.register(event, handler);
+        self.subscribe_for(event);
+        id
+    }
+
+    /// Register a handler that is removed after the event fired once
+    pub fn once<F>(&mut self, event: Event, handler: F) -> HandlerId
+    where
+        F: FnOnce(EventContext) + 'static + Send,
+    {
+        let id = self.event_handler_registry.register_once(event, handler);
+        self.subscribe_for(event);
+        id
+    }
+
+    fn subscribe_for(&self, event: Event) {
+        if event.requires_subscription() {
+            self.connection_manager.ensure_subscribed(event);
+        }
+    }
+
+    /// Remove a handler, returning whether it was still registered
+    pub fn off(&mut self, id: HandlerId) -> bool {
+        self.event_handler_registry.remove(id)
+    }
+
+    /// Register a catch-all handler for frames that no [`Event`] covers
+    pub fn on_unhandled<F>(&mut self, handler: F) -> HandlerId
+    where
+        F: Fn(EventContext) + 'static + Send + Sync,
+    {
+        self.event_handler_registry.register_unhandled(handler)
+    }
+
+    /// Wait until the event is fired and return the context it was fired in
+    ///
+    /// The listener is registered right away, so an event fired before the returned
+    /// future is first polled is not missed. Dropping the future removes the listener.
+    pub fn wait_for_event(
+        &mut self,
+        event: Event,
+    ) -> impl Future<Output = Result<EventContext>> + Send + 'static {
+        let (tx, rx) = oneshot::channel();
+        let id = self.once(event, move |ctx| {
+            tx.send(ctx).ok();
+        });
+        let listener = RemoveOnDrop(self.event_handler_registry.clone(), id);
+
+        async move {
+            let _listener = listener;
+            rx.await.map_err(|_| DiscordError::ConnectionClosed)
+        }
+    }
+
+    event_handler_function!(on_ready, Event::Ready, ReadyEvent);
+
+    event_handler_function!(on_error, Event::Error, ErrorEvent);
+
+    event_handler_function!(on_activity_join, Event::ActivityJoin, ActivityJoinEvent);
+
+    event_handler_function!(
+        on_activity_join_request,
+        Event::ActivityJoinRequest,
+        ActivityJoinRequestEvent
+    );
+
+    event_handler_function!(
+        on_activity_spectate,
+        Event::ActivitySpectate,
+        ActivitySpectateEvent
+    );
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::models::message::OpCode;
+    use serde_json::{json, Value as JsonValue};
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::{UnixListener, UnixStream},
+    };
+
+    async fn read_message(stream: &mut UnixStream) -> Message {
+        let mut header = [0; 8];
+        stream.read_exact(&mut header).await.unwrap();
+        let len = u32::from_le_bytes([header[4], header[5], header[6], header[7]]) as usize;
+        let mut payload = vec![0; len];
+        stream.read_exact(&mut payload).await.unwrap();
+
+        let mut frame = header.to_vec();
+        frame.extend(payload);
+        Message::decode(&frame).unwrap()
+    }
+
+    /// The next frame that is not a `SUBSCRIBE`, which handlers send on their own schedule
+    async fn read_unsubscribed(stream: &mut UnixStream) -> Message {
+        loop {
+            let message = read_message(stream).await;
+            let payload: JsonValue = serde_json::from_str(&message.payload).unwrap();
+            if payload["cmd"] != "SUBSCRIBE" {
+                return message;
+            }
+        }
+    }
+
+    async fn write_message(stream: &mut UnixStream, payload: JsonValue) {
+        let message = Message::new(OpCode::Frame, payload).unwrap();
+        stream.write_all(&message.encode().unwrap()).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn sets_activity_over_tokio_socket() {
+        let dir = std::env::temp_dir().join(crate::utils::nonce());
+        std::fs::create_dir_all(&dir).unwrap();
+        let listener = UnixListener::bind(dir.join("discord-ipc-0")).unwrap();
+
+        let server = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+
+            let handshake = read_message(&mut stream).await;
+            assert_eq!(handshake.opcode, OpCode::Handshake);
+            write_message(
+                &mut stream,
+                json!({
+                    "cmd": "DISPATCH",
+                    "evt": "READY",
+                    "data": { "v": 1, "config": { "api_endpoint": "//ptb.discord.com/api" } }
+                }),
+            )
+            .await;
+
+            let command: JsonValue =
+                serde_json::from_str(&read_unsubscribed(&mut stream).await.payload).unwrap();
+            assert_eq!(command["cmd"], "SET_ACTIVITY");
+            write_message(
+                &mut stream,
+                json!({
+                    "cmd": "SET_ACTIVITY",
+                    "data": command["args"]["activity"],
+                    "nonce": command["nonce"]
+                }),
+            )
+            .await;
+
+            write_message(
+                &mut stream,
+                json!({ "cmd": "DISPATCH", "evt": "ACTIVITY_JOIN", "data": { "secret": "s" } }),
+            )
+            .await;
+
+            let ping = Message::new(OpCode::Ping, json!({ "n": 1 })).unwrap();
+            stream.write_all(&ping.encode().unwrap()).await.unwrap();
+            let pong = read_unsubscribed(&mut stream).await;
+            assert_eq!(pong.opcode, OpCode::Pong);
+            assert_eq!(pong.payload, ping.payload);
+        });
+
+        let mut client = ClientBuilder::new(1).ipc_dir(&dir).build_async().unwrap();
+        client.connect().await.unwrap();
+        assert!(client.is_ready());
+        assert_eq!(
+            client.connection_info().unwrap().build,
+            crate::DiscordBuild::Ptb
+        );
+
+        let join = client.wait_for_event(Event::ActivityJoin);
+        let response = client
+            .set_activity(|a| a.state(Some("async".to_owned())))
+            .await
+            .unwrap();
+        assert_eq!(response.data.unwrap().state.as_deref(), Some("async"));
+
+        assert_eq!(join.await.unwrap().event["secret"], "s");
+
+        server.await.unwrap();
+        client.disconnect().await;
+        assert!(!client.is_ready());
+        std::fs::remove_dir_all(dir).ok();
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn subscribes_for_registered_handlers() {
+        let server = crate::testing::MockServer::start().unwrap();
+        let subscribed = |server: &crate::testing::MockServer, evt: &str| {
+            server
+                .commands()
+                .iter()
+                .any(|command| command["cmd"] == "SUBSCRIBE" && command["evt"] == evt)
+        };
+        let timeout = std::time::Duration::from_secs(5);
+
+        let mut client = server.client_builder(1).build_async().unwrap();
+        client.on_activity_join(|_| {});
+        client.connect().await.unwrap();
+        assert!(server.wait_until(timeout, |server| subscribed(server, "ACTIVITY_JOIN")));
+
+        client.on_activity_spectate(|_| {});
+        assert!(server.wait_until(timeout, |server| subscribed(server, "ACTIVITY_SPECTATE")));
+
+        client
+            .unsubscribe(Event::ActivityJoin, |args| args)
+            .await
+            .unwrap();
+        let subscription = client
+            .subscribe(Event::ActivityJoinRequest, |args| args)
+            .await
+            .unwrap();
+        assert_eq!(
+            subscription.data.unwrap().evt.as_deref(),
+            Some("ACTIVITY_JOIN_REQUEST")
+        );
+
+        // Reconnecting renews every subscription still kept
+        client.disconnect().await;
+        client.connect().await.unwrap();
+        assert!(server.wait_until(timeout, |server| server.commands().len() == 6));
+        let mut renewed: Vec<_> = server.commands()[4..]
+            .iter()
+            .map(|command| command["evt"].as_str().unwrap().to_owned())
+            .collect();
+        renewed.sort();
+        assert_eq!(renewed, vec!["ACTIVITY_JOIN_REQUEST", "ACTIVITY_SPECTATE"]);
+    }
+
+    #[tokio::test]
+    async fn closes_the_connection_on_disconnect_and_drop() {
+        let dir = std::env::temp_dir().join(crate::utils::nonce());
+        std::fs::create_dir_all(&dir).unwrap();
+        let listener = UnixListener::bind(dir.join("discord-ipc-0")).unwrap();
+
+        let server = tokio::spawn(async move {
+            let mut received = Vec::new();
+            for _ in 0..2 {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                assert_eq!(read_message(&mut stream).await.opcode, OpCode::Handshake);
+                write_message(
+                    &mut stream,
+                    json!({ "cmd": "DISPATCH", "evt": "READY", "data": { "v": 1 } }),
+                )
+                .await;
+
+                // Everything the client sends until it hangs up
+                let mut bytes = Vec::new();
+                stream.read_to_end(&mut bytes).await.unwrap();
+                received.push(bytes);
+            }
+            received
+        });
+
+        let mut client = ClientBuilder::new(1).ipc_dir(&dir).build_async().unwrap();
+        client.connect().await.unwrap();
+        client.disconnect().await;
+        assert!(!client.is_ready());
+
+        client.connect().await.unwrap();
+        drop(client);
+
+        let received = tokio::time::timeout(std::time::Duration::from_secs(5), server)
+            .await
+            .expect("the dropped client kept the connection open")
+            .unwrap();
+        assert_eq!(Message::decode(&received[0]).unwrap().opcode, OpCode::Close);
+        assert!(received[1].is_empty());
+    }
+
+    #[test]
+    fn build_async_rejects_blocking_only_options() {
+        let result = ClientBuilder::new(1)
+            .reconnect_policy(crate::ReconnectPolicy::default())
+            .build_async();
+        assert!(matches!(
+            result,
+            Err(DiscordError::BlockingClientOnly("reconnect_policy"))
+        ));
+        assert!(ClientBuilder::new(1).build_async().is_ok());
+    }
+}
@@ -0,0 +1,203 @@
+use crate::{
+    connection::{Config, Connector, DiscordBuild, RateLimit, ReconnectPolicy, Recorder},
+    event_handler::HandlerExecutor,
+    models::ValidationMode,
+    Client,
+};
+#[cfg(feature = "tokio")]
+use crate::{DiscordError, Result};
+use std::{path::PathBuf, sync::Arc, time::Duration};
+
+/// Builder for a [`Client`] that needs more than the default connection settings
+///
+/// ```no_run
+/// use discord_presence::{ClientBuilder, DiscordBuild};
+///
+/// let client = ClientBuilder::new(1003450375732482138)
+///     .preferred_build(DiscordBuild::Canary)
+///     .build();
+/// ```
+#[derive(Debug, Clone)]
+pub struct ClientBuilder {
+    client_id: u64,
+    config: Config,
+    /// Options set that only the blocking [`Client`] honours
+    blocking_only: Vec<&'static str>,
+}
+
+impl ClientBuilder {
+    /// Creates a new `ClientBuilder` for the given application ID
+    pub fn new(client_id: u64) -> Self {
+        Self {
+            client_id,
+            config: Config::default(),
+            blocking_only: Vec::new(),
+        }
+    }
+
+    /// Try the `discord-ipc-N` socket with this index before any other
+    ///
+    /// The remaining sockets are still tried if this one is unavailable.
+    pub fn preferred_socket(mut self, index: u8) -> Self {
+        self.config.preferred_socket = Some(index);
+        self
+    }
+
+    /// Prefer a specific Discord build when several of them are running
+    ///
+    /// Falls back to the first reachable build if the preferred one is not found.
+    pub fn preferred_build(mut self, build: DiscordBuild) -> Self {
+        self.config.preferred_build = Some(build);
+        self
+    }
+
+    /// Look for Discord's sockets in `dir` before the default locations
+    ///
+    /// Can be called multiple times; directories are tried in the order they were added.
+    /// The defaults themselves can be replaced with the `DISCORD_IPC_PATH` environment variable.
+    pub fn ipc_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.config.ipc_dirs.push(dir.into());
+        self
+    }
+
+    /// Largest frame payload accepted from Discord, in bytes
+    ///
+    /// Bigger frames are skipped. Defaults to [`DEFAULT_MAX_FRAME_SIZE`](crate::DEFAULT_MAX_FRAME_SIZE).
+    pub fn max_frame_size(mut self, bytes: usize) -> Self {
+        self.config.max_frame_size = bytes;
+        self
+    }
+
+    /// How long commands such as `set_activity` wait for Discord's response
+    ///
+    /// Also limits how long Discord may take to answer the handshake on a new connection.
+    ///
+    /// Defaults to [`DEFAULT_COMMAND_TIMEOUT`](crate::DEFAULT_COMMAND_TIMEOUT).
+    pub fn command_timeout(mut self, timeout: Duration) -> Self {
+        self.config.command_timeout = timeout;
+        self
+    }
+
+    /// How to retry when connecting fails or the connection drops
+    ///
+    /// The blocking [`Client`] defaults to retrying forever with exponential backoff. The
+    /// async client never reconnects, so [`build_async`](Self::build_async) rejects this option.
+    pub fn reconnect_policy(mut self, policy: ReconnectPolicy) -> Self {
+        self.blocking_only.push("reconnect_policy");
+        self.config.reconnect = policy;
+        self
+    }
+
+    /// How long the connection may stay quiet before Discord is pinged
+    ///
+    /// If the `Pong` does not arrive within the [`heartbeat_timeout`](Self::heartbeat_timeout)
+    /// the connection is dropped and re-established. `None` turns the heartbeat off.
+    /// Defaults to [`DEFAULT_HEARTBEAT_INTERVAL`](crate::DEFAULT_HEARTBEAT_INTERVAL).
+    pub fn heartbeat_interval(mut self, interval: Option<Duration>) -> Self {
+        self.config.heartbeat_interval = interval;
+        self
+    }
+
+    /// How long to wait for Discord to answer a heartbeat `Ping`
+    ///
+    /// Defaults to [`DEFAULT_HEARTBEAT_TIMEOUT`](crate::DEFAULT_HEARTBEAT_TIMEOUT).
+    pub fn heartbeat_timeout(mut self, timeout: Duration) -> Self {
+        self.config.heartbeat_timeout = timeout;
+        self
+    }
+
+    /// Open connections with `connector` instead of looking for Discord's local sockets
+    ///
+    /// Use a [`TcpConnector`](crate::TcpConnector) to reach Discord through a TCP bridge.
+    /// Only the blocking [`Client`] uses the connector, [`build_async`](Self::build_async)
+    /// rejects it.
+    pub fn connector(mut self, connector: impl Connector + 'static) -> Self {
+        self.blocking_only.push("connector");
+        self.config.connector = Arc::new(connector);
+        self
+    }
+
+    /// Write every frame exchanged with Discord to `recorder`
+    ///
+    /// Play the recording back with a [`ReplayConnector`](crate::ReplayConnector) to
+    /// reproduce a session without Discord. Only the blocking [`Client`] records,
+    /// [`build_async`](Self::build_async) rejects this option.
+    pub fn recorder(mut self, recorder: Recorder) -> Self {
+        self.blocking_only.push("recorder");
+        self.config.recorder = Some(recorder);
+        self
+    }
+
+    /// Check activities against Discord's limits before `set_activity` sends them
+    ///
+    /// Discord silently drops activities that break its limits, see
+    /// [`Activity::validate`](crate::models::Activity::validate). Defaults to
+    /// [`ValidationMode::Off`].
+    pub fn activity_validation(mut self, mode: ValidationMode) -> Self {
+        self.config.activity_validation = mode;
+        self
+    }
+
+    /// How often [`Client::queue_activity`] may send activities
+    ///
+    /// Defaults to five updates every 20 seconds, Discord's own limit. Activities set with
+    /// [`Client::set_activity`] are counted but never held back. The async client has no
+    /// queue, so [`build_async`](Self::build_async) rejects this option.
+    pub fn activity_rate_limit(mut self, limit: RateLimit) -> Self {
+        self.blocking_only.push("activity_rate_limit");
+        self.config.activity_rate_limit = limit;
+        self
+    }
+
+    /// Skip activities matching the one Discord already shows
+    ///
+    /// Timestamps may differ by up to `tolerance`, see
+    /// [`Activity::matches`](crate::models::Activity::matches). `None` sends every activity.
+    /// Defaults to skipping exact duplicates only. Only the blocking [`Client`] skips
+    /// activities, [`build_async`](Self::build_async) rejects this option.
+    pub fn skip_duplicate_activities(mut self, tolerance: Option<Duration>) -> Self {
+        self.blocking_only.push("skip_duplicate_activities");
+        self.config.duplicate_activity_tolerance = tolerance;
+        self
+    }
+
+    /// Set the last activity again whenever the client (re)connects
+    ///
+    /// Discord drops the activity along with the connection, e.g. when it restarts. Activities
+    /// set before the first connection are applied once it is established. Defaults to `true`.
+    /// Only the blocking [`Client`] restores activities, [`build_async`](Self::build_async)
+    /// rejects this option.
+    pub fn restore_activity(mut self, restore: bool) -> Self {
+        self.blocking_only.push("restore_activity");
+        self.config.restore_activity = restore;
+        self
+    }
+
+    /// Run event handlers with `executor` instead of on a dedicated thread
+    ///
+    /// Use an [`InlineExecutor`](crate::InlineExecutor) to run them on the thread reading
+    /// from Discord, or implement [`HandlerExecutor`] to hand them to your own thread pool.
+    pub fn handler_executor(mut self, executor: impl HandlerExecutor + 'static) -> Self {
+        self.config.handler_executor = Arc::new(executor);
+        self
+    }
+
+    /// Creates the `Client`
+    pub fn build(self) -> Client {
+        Client::with_config(self.client_id, self.config)
+    }
+
+    /// Creates an [`AsyncClient`](crate::AsyncClient) running on tokio
+    ///
+    /// # Errors
+    ///
+    /// Returns [`DiscordError::BlockingClientOnly`] if an option only the blocking [`Client`]
+    /// honours was set, instead of silently ignoring it.
+    #[cfg(feature = "tokio")]
+    pub fn build_async(self) -> Result<crate::AsyncClient> {
+        if let Some(option) = self.blocking_only.first() {
+            return Err(DiscordError::BlockingClientOnly(option));
+        }
+        Ok(crate::AsyncClient::with_config(self.client_id, self.config))
+    }
+}
@@ -1,40 +1,43 @@
-use std::sync::atomic::Ordering;
-
 use crate::{
-    connection::Manager as ConnectionManager,
-    event_handler::{Context as EventContext, HandlerRegistry},
+    connection::{Config, ConnectionInfo, Manager as ConnectionManager},
+    event_handler::{Context as EventContext, HandlerId, HandlerRegistry},
     models::{
         message::Message,
         payload::Payload,
-        rich_presence::{Activity, SetActivityArgs},
-        Command, Event, OpCode,
+        rich_presence::{
+            Activity, ActivityJoinEvent, ActivityJoinRequestEvent, ActivitySpectateEvent,
+            CloseActivityRequestArgs, SendActivityJoinInviteArgs, SetActivityArgs,
+        },
+        AuthenticateArgs, Authentication, Authorization, AuthorizeArgs, Command, ErrorEvent, Event,
+        OpCode, ReadyEvent, Subscription, SubscriptionArgs, ValidationMode,
     },
-    DiscordError, Result,
+    ClientBuilder, DiscordError, Result,
 };
-use serde::Serialize;
-
-macro_rules! event_handler_function {
-    ( $( $name:ident, $event:expr ),* ) => {
-        event_handler_function!{@gen $([ $name, $event])*}
-    };
-
-    (@gen $( [ $name:ident, $event:expr ] ), *) => {
-        $(
-            #[doc = concat!("Listens for the `", stringify!($event), "` event")]
-            pub fn $name<F>(&mut self, handler: F)
-                where F: Fn(EventContext) + 'static + Send + Sync
-            {
-                self.on_event($event, handler);
-            }
-        )*
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value as JsonValue;
+use std::{error::Error as StdError, sync::Arc, time::Duration};
+
+/// How long [`Client::authorize`] waits for the user to answer Discord's consent prompt
+const AUTHORIZE_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Shuts the connection manager down once the last [`Client`] handle is dropped
+struct ShutdownOnDrop(ConnectionManager);
+
+impl Drop for ShutdownOnDrop {
+    fn drop(&mut self) {
+        self.0.shutdown();
     }
 }
 
 /// The Discord client
+///
+/// Clones share the same connection, which is closed when the last of them is dropped.
 #[derive(Clone)]
 pub struct Client {
     connection_manager: ConnectionManager,
-    event_handler_registry: HandlerRegistry<'static>,
+    event_handler_registry: HandlerRegistry,
+    activity_validation: ValidationMode,
+    _shutdown: Arc<ShutdownOnDrop>,
 }
 
 #[cfg(feature = "bevy")]
@@ -43,90 +46,374 @@ impl bevy::ecs::system::Resource for Client {}
 impl Client {
     /// Creates a new `Client`
     pub fn new(client_id: u64) -> Self {
-        let event_handler_registry = HandlerRegistry::new();
-        let connection_manager = ConnectionManager::new(client_id, event_handler_registry.clone());
+        Self::with_config(client_id, Config::default())
+    }
+
+    /// Creates a [`ClientBuilder`] to customize the connection
+    pub fn builder(client_id: u64) -> ClientBuilder {
+        ClientBuilder::new(client_id)
+    }
+
+    pub(crate) fn with_config(client_id: u64, config: Config) -> Self {
+        let event_handler_registry = HandlerRegistry::new(config.handler_executor.clone());
+        let activity_validation = config.activity_validation;
+        let connection_manager =
+            ConnectionManager::new(client_id, config, event_handler_registry.clone());
         Self {
+            _shutdown: Arc::new(ShutdownOnDrop(connection_manager.clone())),
             connection_manager,
             event_handler_registry,
+            activity_validation,
         }
     }
 
     /// Start the connection manager
     ///
-    /// Only join the thread if there is no other task keeping the program alive.
-    ///
     /// This must be called before all and any actions such as `set_activity`
-    #[must_use]
-    pub fn start(&mut self) -> std::thread::JoinHandle<()> {
-        let thread = self.connection_manager.start();
-
-        crate::STARTED.store(true, Ordering::Relaxed);
+    /// Calling it again while the connection manager is running does nothing.
+    pub fn start(&mut self) {
+        self.connection_manager.start();
+    }
 
-        self.on_ready(|_| {
-            trace!("Discord client is ready!");
-            crate::READY.store(true, Ordering::Relaxed);
-        });
+    /// Close the connection to Discord and stop the connection manager
+    ///
+    /// Sends Discord a `Close` frame and waits for the connection manager's thread to exit.
+    /// Dropping the last handle to the client does the same.
+    pub fn shutdown(&mut self) {
+        self.connection_manager.shutdown();
+    }
 
-        thread
+    /// Block the current thread until the connection manager stops
+    ///
+    /// Only use this if there is no other task keeping the program alive. The manager stops
+    /// after [`shutdown`](Self::shutdown) or once the [`ReconnectPolicy`](crate::ReconnectPolicy)
+    /// gives up.
+    pub fn block_until_shutdown(&self) {
+        self.connection_manager.join();
     }
 
+    /// The application ID this client was created with
     pub fn client_id(&self) -> u64 {
         self.connection_manager.get_client_id()
     }
 
-    /// Check if the client is ready
-    pub fn is_ready() -> bool {
-        crate::READY.load(Ordering::Acquire)
+    /// The socket index and Discord build the client is connected to
+    ///
+    /// Returns `None` while no connection is established.
+    pub fn connection_info(&self) -> Option<ConnectionInfo> {
+        self.connection_manager.connection_info()
+    }
+
+    /// Check if the client is connected and ready
+    pub fn is_ready(&self) -> bool {
+        self.connection_manager.is_ready()
     }
 
     /// Check if the client has started
-    pub fn is_started() -> bool {
-        crate::STARTED.load(Ordering::Acquire)
+    ///
+    /// Turns `false` again once the [`ReconnectPolicy`](crate::ReconnectPolicy) gives up.
+    pub fn is_started(&self) -> bool {
+        self.connection_manager.is_started()
     }
 
-    fn execute<A>(&mut self, cmd: Command, args: A, evt: Option<Event>) -> Result<()>
+    /// Send a command and wait for Discord's response to it
+    ///
+    /// Fails with [`DiscordError::Rpc`] if Discord rejects the command, or with
+    /// [`DiscordError::RecvTimeoutError`] if no response arrives in time. If Discord closed
+    /// the connection since the last command, the reason is returned as [`DiscordError::Closed`].
+    fn execute<A, E>(&mut self, cmd: Command, args: A, evt: Option<Event>) -> Result<Payload<E>>
     where
         A: Serialize + Send + Sync,
+        E: Serialize + DeserializeOwned,
     {
-        if !crate::STARTED.load(Ordering::Relaxed) || !crate::READY.load(Ordering::Relaxed) {
+        self.execute_within(cmd, args, evt, None)
+    }
+
+    /// [`execute`](Self::execute) with a timeout other than the configured one
+    fn execute_within<A, E>(
+        &mut self,
+        cmd: Command,
+        args: A,
+        evt: Option<Event>,
+        timeout: Option<Duration>,
+    ) -> Result<Payload<E>>
+    where
+        A: Serialize + Send + Sync,
+        E: Serialize + DeserializeOwned,
+    {
+        let (nonce, message) = self.command_message(cmd, args, evt)?;
+        let response = self.connection_manager.execute(nonce, message, timeout)?;
+
+        Ok(serde_json::from_value(response)?)
+    }
+
+    /// [`execute`](Self::execute) for `SET_ACTIVITY`, counted against the activity rate limit
+    /// once sent
+    fn execute_activity(&mut self, args: SetActivityArgs) -> Result<Payload<Activity>> {
+        let (nonce, message) = self.command_message(Command::SetActivity, args, None)?;
+        let response = self
+            .connection_manager
+            .execute_activity(nonce, message, None)?;
+
+        Ok(serde_json::from_value(response)?)
+    }
+
+    /// Build the frame for a command, failing if it cannot be sent right now
+    fn command_message<A>(
+        &mut self,
+        cmd: Command,
+        args: A,
+        evt: Option<Event>,
+    ) -> Result<(String, Message)>
+    where
+        A: Serialize + Send + Sync,
+    {
+        if let Some(why) = self.connection_manager.take_close_error() {
+            return Err(why);
+        }
+
+        if !self.is_started() || !self.is_ready() {
             return Err(DiscordError::NotStarted);
         }
 
         trace!("Executing command: {:?}", cmd);
-        let message = Message::new(
-            OpCode::Frame,
-            Payload::with_nonce(cmd, Some(args), None, evt),
-        );
-
-        self.connection_manager.send(message?)?;
+        let payload = Payload::with_nonce(cmd, Some(args), None, evt);
+        let nonce = into_error!(payload.nonce.clone())?;
+        let message = Message::new(OpCode::Frame, payload)?;
 
-        Ok(())
+        Ok((nonce, message))
     }
 
     /// Set the users current activity
-    pub fn set_activity<F>(&mut self, f: F) -> Result<()>
+    ///
+    /// Returns the activity as Discord applied it.
+    /// Fails with [`DiscordError::InvalidActivity`] if the activity breaks Discord's limits
+    /// and [`ValidationMode::Reject`] is set.
+    ///
+    /// Nothing is sent if Discord already shows the same activity, see
+    /// [`ClientBuilder::skip_duplicate_activities`]; the activity is returned as given then.
+    /// The activity is set again after reconnecting, even if this call failed, unless
+    /// [`ClientBuilder::restore_activity`] turned that off.
+    pub fn set_activity<F>(&mut self, f: F) -> Result<Payload<Activity>>
+    where
+        F: FnOnce(Activity) -> Activity,
+    {
+        let activity = self.activity_validation.apply(f(Activity::new()))?;
+        self.connection_manager
+            .request_activity(Some(activity.clone()));
+        if self.connection_manager.already_applied(&activity) {
+            trace!("Skipping unchanged activity");
+            return Ok(Payload {
+                cmd: Command::SetActivity,
+                args: None,
+                data: Some(activity),
+                evt: None,
+                nonce: None,
+            });
+        }
+
+        let response = self.execute_activity(SetActivityArgs::new(|_| activity.clone()))?;
+        self.connection_manager.activity_applied(Some(activity));
+
+        Ok(response)
+    }
+
+    /// Queue an activity update, sending it as soon as the rate limit allows
+    ///
+    /// Unlike [`set_activity`](Self::set_activity) this returns right away. Updates within
+    /// the [`RateLimit`](crate::RateLimit) go out immediately; beyond it only the latest
+    /// queued activity is kept and sent once the limit allows. Activities queued before the
+    /// client is connected are sent after the handshake. Discord's answer is not awaited,
+    /// refusals are logged. Activities Discord already shows are skipped like in
+    /// [`set_activity`](Self::set_activity).
+    pub fn queue_activity<F>(&mut self, f: F) -> Result<()>
     where
         F: FnOnce(Activity) -> Activity,
     {
-        self.execute(Command::SetActivity, SetActivityArgs::new(f), None)
+        let activity = self.activity_validation.apply(f(Activity::new()))?;
+        if self.connection_manager.already_applied(&activity) {
+            trace!("Skipping unchanged activity");
+            return Ok(());
+        }
+
+        self.connection_manager.queue_activity(activity)
+    }
+
+    /// Send the next activity even if Discord already shows it
+    ///
+    /// The client forgets the activity whenever the connection drops, so this is only needed
+    /// if something else may have changed it, e.g. another client with the same application ID.
+    pub fn force_activity_resend(&mut self) {
+        self.connection_manager.activity_applied(None);
     }
 
     /// Clear the users current activity
-    pub fn clear_activity(&mut self) -> Result<()> {
-        self.execute(Command::SetActivity, SetActivityArgs::default(), None)
+    pub fn clear_activity(&mut self) -> Result<Payload<Activity>> {
+        self.connection_manager.request_activity(None);
+        let response = self.execute_activity(SetActivityArgs::default())?;
+        self.connection_manager.activity_applied(None);
+
+        Ok(response)
+    }
+
+    /// Accept a request to join the user's game, sent through [`Event::ActivityJoinRequest`]
+    ///
+    /// Take the `user_id` from the request with
+    /// [`ActivityJoinRequestEvent::user_id`](crate::models::ActivityJoinRequestEvent::user_id).
+    /// Waits for Discord's answer, so do not call it from a handler run by an
+    /// [`InlineExecutor`](crate::InlineExecutor).
+    pub fn accept_join_request(&mut self, user_id: u64) -> Result<Payload<JsonValue>> {
+        self.execute(
+            Command::SendActivityJoinInvite,
+            SendActivityJoinInviteArgs::new(user_id),
+            None,
+        )
     }
 
+    /// Reject a request to join the user's game, sent through [`Event::ActivityJoinRequest`]
+    ///
+    /// Waits for Discord's answer, so do not call it from a handler run by an
+    /// [`InlineExecutor`](crate::InlineExecutor).
+    pub fn reject_join_request(&mut self, user_id: u64) -> Result<Payload<JsonValue>> {
+        self.execute(
+            Command::CloseActivityRequest,
+            CloseActivityRequestArgs::new(user_id),
+            None,
+        )
+    }
+
+    /// Ask the user to authorize the application for `scopes` and return the OAuth2 code
+    ///
+    /// Discord shows the user a consent prompt, so this waits up to five minutes. Use `f` to
+    /// set further arguments such as the `rpc_token`. Exchange the code for an access token
+    /// with Discord's OAuth2 API, then pass it to [`authenticate`](Self::authenticate).
+    pub fn authorize<F>(&mut self, scopes: &[&str], f: F) -> Result<String>
+    where
+        F: FnOnce(AuthorizeArgs) -> AuthorizeArgs,
+    {
+        let args = f(AuthorizeArgs::new())
+            .client_id(Some(self.client_id().to_string()))
+            .scopes(Some(scopes.iter().map(|&scope| scope.to_owned()).collect()));
+
+        let response: Payload<Authorization> =
+            self.execute_within(Command::Authorize, args, None, Some(AUTHORIZE_TIMEOUT))?;
+
+        into_error!(
+            response.data.and_then(|data| data.code),
+            String::from("Discord did not return an authorization code")
+        )
+    }
+
+    /// Authenticate with an OAuth2 access token, unlocking the commands its scopes allow
+    ///
+    /// Returns the authenticated user and application.
+    pub fn authenticate(&mut self, access_token: &str) -> Result<Authentication> {
+        let args = AuthenticateArgs::new().access_token(Some(access_token.to_owned()));
+        let response: Payload<Authentication> = self.execute(Command::Authenticate, args, None)?;
+
+        into_error!(response.data)
+    }
+
+    /// Run the whole OAuth2 flow: [`authorize`](Self::authorize), exchange the code, then
+    /// [`authenticate`](Self::authenticate)
+    ///
+    /// `exchange` turns the authorization code into an access token, typically by calling a
+    /// backend that holds the application's client secret.
+    pub fn login<F, E>(&mut self, scopes: &[&str], exchange: F) -> Result<Authentication>
+    where
+        F: FnOnce(&str) -> std::result::Result<String, E>,
+        E: Into<Box<dyn StdError + Send + Sync>>,
+    {
+        let code = self.authorize(scopes, |args| args)?;
+        let access_token =
+            exchange(&code).map_err(|why| DiscordError::TokenExchange(why.into()))?;
+
+        self.authenticate(&access_token)
+    }
+
+    /// Clear the users current activity and shut the connection down
     pub fn clear(&mut self) {
         self.clear_activity().ok();
-        self.connection_manager.stop();
+        self.shutdown();
+    }
+
+    /// Subscribe to an event, renewing the subscription whenever the client reconnects
+    ///
+    /// Registering a handler for an event that needs a subscription does this automatically.
+    pub fn subscribe<F>(&mut self, event: Event, f: F) -> Result<Payload<Subscription>>
+    where
+        F: FnOnce(SubscriptionArgs) -> SubscriptionArgs,
+    {
+        let args = f(SubscriptionArgs::new());
+        let response = self.execute(Command::Subscribe, &args, Some(event))?;
+        self.connection_manager.keep_subscription(event, args);
+
+        Ok(response)
+    }
+
+    /// Unsubscribe from an event
+    ///
+    /// Handlers registered for the event stay registered but are no longer called.
+    pub fn unsubscribe<F>(&mut self, event: Event, f: F) -> Result<Payload<Subscription>>
+    where
+        F: FnOnce(SubscriptionArgs) -> SubscriptionArgs,
+    {
+        self.connection_manager.forget_subscription(event);
+        self.execute(
+            Command::Unsubscribe,
+            f(SubscriptionArgs::new()),
+            Some(event),
+        )
     }
 
     /// Register a handler for a given event
-    pub fn on_event<F>(&mut self, event: Event, handler: F)
+    ///
+    /// Events Discord only sends to subscribers, like [`Event::ActivityJoin`], are subscribed
+    /// to as soon as the client is connected. Returns an ID to remove the handler with
+    /// [`off`](Self::off).
+    pub fn on_event<F>(&mut self, event: Event, handler: F) -> HandlerId
     where
         F: Fn(EventContext) + 'static + Send + Sync,
     {
-        self.event_handler_registry.register(event, handler);
+        let id = self.event_handler_registry.register(event, handler);
+        self.subscribe_for(event);
+        id
+    }
+
+    /// Register a handler that is removed after the event fired once
+    pub fn once<F>(&mut self, event: Event, handler: F) -> HandlerId
+    where
+        F: FnOnce(EventContext) + 'static + Send,
+    {
+        let id = self.event_handler_registry.register_once(event, handler);
+        self.subscribe_for(event);
+        id
+    }
+
+    /// Remove a handler, returning whether it was still registered
+    ///
+    /// Subscriptions made for the handler's event are kept.
+    pub fn off(&mut self, id: HandlerId) -> bool {
+        self.event_handler_registry.remove(id)
+    }
+
+    fn subscribe_for(&self, event: Event) {
+        if event.requires_subscription() {
+            if let Err(why) = self.connection_manager.ensure_subscribed(event) {
+                error!("Failed to subscribe to {:?}: {}", event, why);
+            }
+        }
+    }
+
+    /// Register a catch-all handler for frames that no [`Event`] covers
+    ///
+    /// The handler receives the whole raw payload, e.g. dispatches of events this crate
+    /// does not model yet.
+    pub fn on_unhandled<F>(&mut self, handler: F) -> HandlerId
+    where
+        F: Fn(EventContext) + 'static + Send + Sync,
+    {
+        self.event_handler_registry.register_unhandled(handler)
     }
 
     /// Block the current thread until the event is fired
@@ -134,29 +421,51 @@ impl Client {
     /// Returns the context the event was fired in
     ///
     /// NOTE: Please only use this for the ready event, or if you know what you are doing.
-    ///
-    /// # Panics
-    ///
-    /// Panics if the channel is disconnected for whatever reason.
-    pub fn block_until_event(&mut self, event: Event) -> Result<crate::event_handler::Context> {
-        let (tx, rx) = crossbeam_channel::bounded::<crate::event_handler::Context>(1);
+    pub fn block_until_event(&mut self, event: Event) -> Result<EventContext> {
+        let (tx, rx) = crossbeam_channel::bounded::<EventContext>(1);
+        self.once(event, move |info| {
+            tx.send(info).ok();
+        });
+
+        Ok(rx.recv()?)
+    }
 
-        let handler = move |info| tx.send(info).unwrap();
+    /// Block the current thread until the event is fired, giving up after `timeout`
+    ///
+    /// Fails with [`DiscordError::RecvTimeoutError`] if the event did not fire in time.
+    pub fn block_until_event_timeout(
+        &mut self,
+        event: Event,
+        timeout: Duration,
+    ) -> Result<EventContext> {
+        let (tx, rx) = crossbeam_channel::bounded::<EventContext>(1);
+        let id = self.once(event, move |info| {
+            tx.send(info).ok();
+        });
 
-        self.event_handler_registry.register(event, handler);
+        let result = rx.recv_timeout(timeout);
+        self.off(id);
 
-        Ok(rx.recv()?)
+        Ok(result?)
     }
 
-    event_handler_function!(on_ready, Event::Ready);
+    event_handler_function!(on_ready, Event::Ready, ReadyEvent);
 
-    event_handler_function!(on_error, Event::Error);
+    event_handler_function!(on_error, Event::Error, ErrorEvent);
 
-    event_handler_function!(on_activity_join, Event::ActivityJoin);
+    event_handler_function!(on_activity_join, Event::ActivityJoin, ActivityJoinEvent);
 
-    event_handler_function!(on_activity_join_request, Event::ActivityJoinRequest);
+    event_handler_function!(
+        on_activity_join_request,
+        Event::ActivityJoinRequest,
+        ActivityJoinRequestEvent
+    );
 
-    event_handler_function!(on_activity_spectate, Event::ActivitySpectate);
+    event_handler_function!(
+        on_activity_spectate,
+        Event::ActivitySpectate,
+        ActivitySpectateEvent
+    );
 }
 
 #[cfg(test)]
@@ -165,19 +474,452 @@ mod tests {
 
     #[test]
     fn test_is_started() {
-        assert!(!Client::is_started());
+        let client = Client::new(1);
 
-        crate::STARTED.store(true, Ordering::Relaxed);
-
-        assert!(Client::is_started());
+        assert!(!client.is_started());
     }
 
     #[test]
     fn test_is_ready() {
-        assert!(!Client::is_ready());
+        let client = Client::new(1);
+
+        assert!(!client.is_ready());
+        assert!(!client.clone().is_ready());
+    }
+
+    #[cfg(unix)]
+    mod mock {
+        use super::*;
+        use crate::{testing::MockServer, CloseCode, ReconnectPolicy};
+        use serde_json::json;
+        use std::time::Duration;
+
+        const TIMEOUT: Duration = Duration::from_secs(5);
+
+        fn connected(builder: ClientBuilder) -> Client {
+            let mut client = builder.build();
+            client.start();
+
+            for _ in 0..(TIMEOUT.as_millis() / 10) {
+                if client.is_ready() {
+                    return client;
+                }
+                std::thread::sleep(Duration::from_millis(10));
+            }
+            panic!("client did not connect to the mock server");
+        }
+
+        #[test]
+        fn sets_and_clears_activity() {
+            let server = MockServer::start().unwrap();
+            let mut client = connected(server.client_builder(1));
+
+            let applied = client
+                .set_activity(|a| a.state(Some("mocked".to_owned())))
+                .unwrap();
+            assert_eq!(applied.data.unwrap().state.as_deref(), Some("mocked"));
+            client.clear_activity().unwrap();
+
+            assert_eq!(
+                server.activities(),
+                vec![json!({ "state": "mocked" }), serde_json::Value::Null]
+            );
+        }
+
+        #[test]
+        fn rate_limits_queued_activities() {
+            let server = MockServer::start().unwrap();
+            let mut client = connected(server.client_builder(1).activity_rate_limit(
+                crate::RateLimit {
+                    updates: 1,
+                    per: Duration::from_millis(500),
+                },
+            ));
+
+            for state in &["first", "second", "third"] {
+                client
+                    .queue_activity(|a| a.state(Some((*state).to_owned())))
+                    .unwrap();
+            }
+            assert!(server.wait_until(TIMEOUT, |server| server.activities().len() == 2));
 
-        crate::READY.store(true, Ordering::Relaxed);
+            assert_eq!(
+                server.activities(),
+                vec![json!({ "state": "first" }), json!({ "state": "third" })]
+            );
+        }
+
+        #[test]
+        fn failed_activity_updates_leave_the_rate_limit_alone() {
+            let server = MockServer::start().unwrap();
+            let mut client = server
+                .client_builder(1)
+                .activity_rate_limit(crate::RateLimit {
+                    updates: 1,
+                    per: Duration::from_secs(60),
+                })
+                .restore_activity(false)
+                .build();
+
+            client
+                .queue_activity(|a| a.state(Some("queued".to_owned())))
+                .unwrap();
+            for _ in 0..3 {
+                assert!(matches!(
+                    client.set_activity(|a| a.state(Some("failed".to_owned()))),
+                    Err(DiscordError::NotStarted)
+                ));
+            }
+            client.start();
+
+            assert!(server.wait_until(TIMEOUT, |server| server.activities().len() == 1));
+            assert_eq!(server.activities(), vec![json!({ "state": "queued" })]);
+        }
+
+        #[test]
+        fn skips_unchanged_activities() {
+            let server = MockServer::start().unwrap();
+            let mut client = connected(
+                server
+                    .client_builder(1)
+                    .skip_duplicate_activities(Some(Duration::from_secs(2))),
+            );
+            let watching = |start| {
+                move |a: Activity| {
+                    a.state(Some("Watching".to_owned()))
+                        .timestamps(|t| t.start(Some(start)))
+                }
+            };
+
+            client.set_activity(watching(1_700_000_000_000)).unwrap();
+            let skipped = client.set_activity(watching(1_700_000_001_000)).unwrap();
+            assert_eq!(skipped.nonce, None);
+            client.queue_activity(watching(1_700_000_001_500)).unwrap();
+            client.set_activity(watching(1_700_000_005_000)).unwrap();
+            client.force_activity_resend();
+            client.set_activity(watching(1_700_000_005_000)).unwrap();
+
+            let starts: Vec<_> = server
+                .activities()
+                .iter()
+                .map(|activity| activity["timestamps"]["start"].clone())
+                .collect();
+            assert_eq!(
+                starts,
+                vec![
+                    json!(1_700_000_000_000u64),
+                    json!(1_700_000_005_000u64),
+                    json!(1_700_000_005_000u64)
+                ]
+            );
+        }
+
+        #[test]
+        fn restores_activity_after_reconnecting() {
+            let server = MockServer::start().unwrap();
+            let mut client = server
+                .client_builder(1)
+                .reconnect_policy(ReconnectPolicy {
+                    initial_delay: Duration::from_millis(10),
+                    ..ReconnectPolicy::default()
+                })
+                .build();
+
+            assert!(matches!(
+                client.set_activity(|a| a.state(Some("early".to_owned()))),
+                Err(DiscordError::NotStarted)
+            ));
+            client.start();
+            assert!(server.wait_until(TIMEOUT, |server| server.activities().len() == 1));
+
+            server.close(CloseCode::Normal, "restarting").unwrap();
+            assert!(server.wait_until(TIMEOUT, |server| server.activities().len() == 2));
+            assert_eq!(
+                server.activities(),
+                vec![json!({ "state": "early" }), json!({ "state": "early" })]
+            );
+            assert_eq!(server.connections(), 2);
+        }
+
+        #[test]
+        fn validates_activities_before_sending() {
+            let server = MockServer::start().unwrap();
+            let long_state = Some("x".repeat(200));
+
+            let mut rejecting = connected(
+                server
+                    .client_builder(1)
+                    .activity_validation(ValidationMode::Reject),
+            );
+            assert!(matches!(
+                rejecting.set_activity(|a| a.state(long_state.clone())),
+                Err(DiscordError::InvalidActivity(_))
+            ));
+            rejecting.shutdown();
+            assert!(server.activities().is_empty());
+
+            let mut sanitizing = connected(
+                server
+                    .client_builder(1)
+                    .activity_validation(ValidationMode::Sanitize),
+            );
+            sanitizing
+                .set_activity(|a| a.state(long_state.clone()))
+                .unwrap();
+            assert_eq!(
+                server.activities()[0]["state"]
+                    .as_str()
+                    .unwrap()
+                    .chars()
+                    .count(),
+                128
+            );
+        }
+
+        #[test]
+        fn receives_dispatched_events() {
+            let server = MockServer::start().unwrap();
+            let mut client = connected(server.client_builder(1));
+
+            let (tx, rx) = crossbeam_channel::bounded(1);
+            client.on_activity_join(move |ctx| tx.send(ctx.raw).unwrap());
+            server
+                .dispatch(Event::ActivityJoin, json!({ "secret": "s" }))
+                .unwrap();
+
+            assert_eq!(rx.recv_timeout(TIMEOUT).unwrap(), json!({ "secret": "s" }));
+        }
 
-        assert!(Client::is_ready());
+        #[test]
+        fn subscribes_for_registered_handlers() {
+            let server = MockServer::start().unwrap();
+            let mut client = server.client_builder(1).build();
+            client.on_activity_join(|_| {});
+            client.on_ready(|_| {});
+            client.start();
+
+            let subscribed = |server: &MockServer, evt: &str| {
+                server
+                    .commands()
+                    .iter()
+                    .any(|command| command["cmd"] == "SUBSCRIBE" && command["evt"] == evt)
+            };
+            assert!(server.wait_until(TIMEOUT, |server| subscribed(server, "ACTIVITY_JOIN")));
+
+            client.on_activity_spectate(|_| {});
+            assert!(server.wait_until(TIMEOUT, |server| subscribed(server, "ACTIVITY_SPECTATE")));
+            assert_eq!(
+                server
+                    .commands()
+                    .iter()
+                    .filter(|command| command["cmd"] == "SUBSCRIBE")
+                    .count(),
+                2
+            );
+        }
+
+        #[test]
+        fn subscribes_and_unsubscribes() {
+            let server = MockServer::start().unwrap();
+            let mut client = connected(server.client_builder(1));
+
+            let subscribed = client
+                .subscribe(Event::ActivityJoinRequest, |args| args)
+                .unwrap();
+            assert_eq!(
+                subscribed.data.unwrap().evt.as_deref(),
+                Some("ACTIVITY_JOIN_REQUEST")
+            );
+            client
+                .unsubscribe(Event::ActivityJoinRequest, |args| args)
+                .unwrap();
+
+            let commands = server.commands();
+            assert_eq!(commands[0]["cmd"], "SUBSCRIBE");
+            assert_eq!(commands[1]["cmd"], "UNSUBSCRIBE");
+            assert_eq!(commands[1]["evt"], "ACTIVITY_JOIN_REQUEST");
+        }
+
+        #[test]
+        fn answers_join_requests() {
+            let server = MockServer::start().unwrap();
+            let mut client = connected(server.client_builder(1));
+
+            let (tx, rx) = crossbeam_channel::bounded(1);
+            client.on_activity_join_request(move |ctx| {
+                tx.send(ctx.event.user_id().unwrap()).unwrap();
+            });
+            server
+                .dispatch(
+                    Event::ActivityJoinRequest,
+                    json!({ "user": { "id": "42", "username": "friend" } }),
+                )
+                .unwrap();
+
+            let user_id = rx.recv_timeout(TIMEOUT).unwrap();
+            client.accept_join_request(user_id).unwrap();
+            client.reject_join_request(user_id).unwrap();
+
+            let answers: Vec<_> = server
+                .commands()
+                .into_iter()
+                .filter(|command| command["cmd"] != "SUBSCRIBE")
+                .map(|command| (command["cmd"].clone(), command["args"].clone()))
+                .collect();
+            assert_eq!(
+                answers,
+                vec![
+                    (
+                        json!("SEND_ACTIVITY_JOIN_INVITE"),
+                        json!({ "user_id": "42" })
+                    ),
+                    (json!("CLOSE_ACTIVITY_REQUEST"), json!({ "user_id": "42" })),
+                ]
+            );
+        }
+
+        #[test]
+        fn logs_in_through_exchange_callback() {
+            let server = MockServer::start().unwrap();
+            let mut client = connected(server.client_builder(7));
+
+            let authentication = client
+                .login(&["rpc", "identify"], |code| {
+                    assert_eq!(code, "mock-code");
+                    Ok::<_, DiscordError>("token".to_owned())
+                })
+                .unwrap();
+            assert_eq!(authentication.access_token.as_deref(), Some("token"));
+            assert_eq!(
+                authentication.user.unwrap().username.as_deref(),
+                Some("mock")
+            );
+
+            let commands = server.commands();
+            assert_eq!(commands[0]["cmd"], "AUTHORIZE");
+            assert_eq!(
+                commands[0]["args"],
+                json!({ "client_id": "7", "scopes": ["rpc", "identify"] })
+            );
+            assert_eq!(commands[1]["cmd"], "AUTHENTICATE");
+
+            let failed = client.login(&["rpc"], |_| Err("no backend"));
+            assert!(matches!(failed, Err(DiscordError::TokenExchange(_))));
+        }
+
+        #[test]
+        fn waits_for_events_with_a_timeout() {
+            let server = MockServer::start().unwrap();
+            let mut client = connected(server.client_builder(1));
+
+            let timed_out =
+                client.block_until_event_timeout(Event::ActivityJoin, Duration::from_millis(50));
+            assert!(matches!(timed_out, Err(DiscordError::RecvTimeoutError(_))));
+
+            let (tx, rx) = crossbeam_channel::unbounded();
+            let removed = client.on_activity_join(|_| panic!("handler was removed"));
+            assert!(client.off(removed));
+            client.once(Event::ActivityJoin, move |ctx| tx.send(ctx.raw).unwrap());
+            for secret in &["first", "second"] {
+                server
+                    .dispatch(Event::ActivityJoin, json!({ "secret": secret }))
+                    .unwrap();
+            }
+
+            let waiter = {
+                let mut client = client.clone();
+                std::thread::spawn(move || client.block_until_event_timeout(Event::Error, TIMEOUT))
+            };
+            server.close(CloseCode::Normal, "bye").unwrap();
+
+            assert_eq!(waiter.join().unwrap().unwrap().raw["message"], "bye");
+            assert_eq!(
+                rx.try_iter().collect::<Vec<_>>(),
+                vec![json!({ "secret": "first" })]
+            );
+        }
+
+        #[test]
+        fn commands_succeed_after_reconnecting() {
+            let server = MockServer::start().unwrap();
+            let mut client =
+                connected(server.client_builder(1).reconnect_policy(ReconnectPolicy {
+                    initial_delay: Duration::from_millis(10),
+                    ..ReconnectPolicy::default()
+                }));
+
+            server.close(CloseCode::Normal, "restarting").unwrap();
+            assert!(server.wait_until(TIMEOUT, |server| server.connections() == 2));
+            for _ in 0..(TIMEOUT.as_millis() / 10) {
+                if client.is_ready() {
+                    break;
+                }
+                std::thread::sleep(Duration::from_millis(10));
+            }
+
+            client
+                .set_activity(|a| a.state(Some("after restart".to_owned())))
+                .unwrap();
+            assert_eq!(
+                server.activities(),
+                vec![json!({ "state": "after restart" })]
+            );
+        }
+
+        #[test]
+        fn gives_up_on_unanswered_handshakes() {
+            let server = MockServer::start().unwrap();
+            server.set_silent(true);
+
+            let mut impatient = server
+                .client_builder(1)
+                .command_timeout(Duration::from_millis(200))
+                .reconnect_policy(ReconnectPolicy::never())
+                .build();
+            impatient.start();
+            for _ in 0..(TIMEOUT.as_millis() / 10) {
+                if !impatient.is_started() {
+                    break;
+                }
+                std::thread::sleep(Duration::from_millis(10));
+            }
+            assert!(!impatient.is_started());
+
+            // Shutting down must not wait out the handshake timeout
+            let mut waiting = server.client_builder(1).build();
+            waiting.start();
+            std::thread::sleep(Duration::from_millis(200));
+            let shutdown = std::time::Instant::now();
+            waiting.shutdown();
+            assert!(shutdown.elapsed() < Duration::from_secs(2));
+            assert!(!waiting.is_started());
+            assert_eq!(server.connections(), 0);
+        }
+
+        #[test]
+        fn reports_close_frames() {
+            let server = MockServer::start().unwrap();
+            let mut client = connected(
+                server
+                    .client_builder(1)
+                    .reconnect_policy(ReconnectPolicy::never()),
+            );
+
+            let (tx, rx) = crossbeam_channel::bounded(1);
+            client.on_error(move |ctx| tx.send(ctx.raw).unwrap());
+            server.close(CloseCode::RateLimited, "Slow down").unwrap();
+
+            assert_eq!(
+                rx.recv_timeout(TIMEOUT).unwrap(),
+                json!({ "code": 4002, "message": "Slow down" })
+            );
+            assert!(matches!(
+                client.clear_activity(),
+                Err(DiscordError::Closed {
+                    code: CloseCode::RateLimited,
+                    ..
+                })
+            ));
+        }
     }
 }
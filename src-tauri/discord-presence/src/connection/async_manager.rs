@@ -0,0 +1,450 @@
+use super::{
+    base::{close_error, close_message, handshake_message},
+    heartbeat::{ping_message, pong_message, Beat, Heartbeat},
+    manager::{
+        handle_inbound, keep_error, log_refusal, ready_data, report_close, subscribe_message,
+        take_close_error, LastClose, Subscriptions,
+    },
+    pending::PendingRequests,
+    reader::FrameReader,
+    Config, ConnectionInfo, Connector, IpcConnector,
+};
+use crate::{
+    error::{DiscordError, Result},
+    event_handler::HandlerRegistry,
+    models::{Event, Message, OpCode, SubscriptionArgs},
+};
+use crossbeam_channel::RecvTimeoutError;
+use parking_lot::RwLock;
+use serde_json::Value as JsonValue;
+use std::{
+    collections::HashMap,
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Instant,
+};
+use tokio::{
+    io::{split, AsyncRead, AsyncReadExt, AsyncWriteExt, ReadHalf, WriteHalf},
+    sync::{oneshot, Mutex},
+    task::JoinHandle,
+};
+
+#[cfg(unix)]
+type IpcStream = tokio::net::UnixStream;
+#[cfg(windows)]
+type IpcStream = tokio::net::windows::named_pipe::NamedPipeClient;
+
+type Candidate = (
+    ReadHalf<IpcStream>,
+    WriteHalf<IpcStream>,
+    FrameReader,
+    ConnectionInfo,
+    JsonValue,
+);
+
+const READ_CHUNK_SIZE: usize = 4096;
+
+/// Connection manager running on the tokio runtime instead of its own thread
+#[derive(Clone)]
+pub struct AsyncManager {
+    client_id: u64,
+    config: Config,
+    event_handler_registry: HandlerRegistry,
+    pending: PendingRequests,
+    last_close: LastClose,
+    subscriptions: Subscriptions,
+    writer: Arc<Mutex<Option<WriteHalf<IpcStream>>>>,
+    reader_task: Arc<parking_lot::Mutex<Option<JoinHandle<()>>>>,
+    connection_info: Arc<RwLock<Option<ConnectionInfo>>>,
+    ready: Arc<AtomicBool>,
+}
+
+impl AsyncManager {
+    pub fn new(client_id: u64, config: Config, event_handler_registry: HandlerRegistry) -> Self {
+        Self {
+            client_id,
+            config,
+            event_handler_registry,
+            pending: PendingRequests::default(),
+            last_close: Arc::new(parking_lot::Mutex::new(None)),
+            subscriptions: Arc::new(parking_lot::Mutex::new(HashMap::new())),
+            writer: Arc::new(Mutex::new(None)),
+            reader_task: Arc::new(parking_lot::Mutex::new(None)),
+            connection_info: Arc::new(RwLock::new(None)),
+            ready: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    pub fn get_client_id(&self) -> u64 {
+        self.client_id
+    }
+
+    pub fn connection_info(&self) -> Option<ConnectionInfo> {
+        self.connection_info.read().clone()
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.ready.load(Ordering::Acquire)
+    }
+
+    /// The reason Discord gave for closing the connection, if it did since the last call
+    pub fn take_close_error(&self) -> Option<DiscordError> {
+        take_close_error(&self.last_close)
+    }
+
+    /// Subscribe to `event` on this and every later connection, unless already subscribed
+    ///
+    /// Does not wait for Discord's answer. While connected the subscription is sent from a
+    /// task on the current tokio runtime; outside of one it is sent on the next `connect`.
+    pub fn ensure_subscribed(&self, event: Event) {
+        let args = SubscriptionArgs::default();
+        {
+            let mut subscriptions = self.subscriptions.lock();
+            if subscriptions.contains_key(&event) {
+                return;
+            }
+            subscriptions.insert(event, args.clone());
+        }
+
+        if !self.is_ready() {
+            return;
+        }
+
+        match tokio::runtime::Handle::try_current() {
+            Ok(runtime) => {
+                let manager = self.clone();
+                runtime.spawn(async move { manager.subscribe(event, &args).await });
+            }
+            Err(_) => trace!("Subscribing to {:?} on the next connection", event),
+        }
+    }
+
+    /// Renew the subscription to `event` with `args` whenever the client connects
+    pub fn keep_subscription(&self, event: Event, args: SubscriptionArgs) {
+        self.subscriptions.lock().insert(event, args);
+    }
+
+    /// Stop renewing the subscription to `event`
+    pub fn forget_subscription(&self, event: Event) {
+        self.subscriptions.lock().remove(&event);
+    }
+
+    /// Send a `SUBSCRIBE` without waiting for the answer, logging refusals.
+    async fn subscribe(&self, event: Event, args: &SubscriptionArgs) {
+        let result = match subscribe_message(event, args) {
+            Ok((nonce, message)) => {
+                self.pending
+                    .register_with(nonce, move |response| log_refusal(event, response));
+                self.send(&message).await
+            }
+            Err(why) => Err(why),
+        };
+
+        if let Err(why) = result {
+            error!("Failed to subscribe to {:?}: {}", event, why);
+        }
+    }
+
+    /// Find a Discord socket, perform the handshake and start reading from it.
+    pub async fn connect(&self) -> Result<()> {
+        if self.is_ready() {
+            return Ok(());
+        }
+
+        trace!("Connecting");
+
+        // The configured connector opens blocking streams, tokio needs its own sockets
+        let endpoints = IpcConnector.endpoints(self.config.preferred_socket, &self.config.ipc_dirs);
+        let mut fallback = None;
+        let mut last_error = None;
+
+        for endpoint in endpoints {
+            let path = endpoint.path;
+            trace!("Probing {}", path.display());
+
+            let candidate = match self.try_socket(endpoint.index, &path).await {
+                Ok(candidate) => candidate,
+                Err(why) => {
+                    trace!("Skipping {}: {}", path.display(), why);
+                    keep_error(&mut last_error, why);
+                    continue;
+                }
+            };
+
+            let build = candidate.3.build;
+            match self.config.preferred_build {
+                Some(preferred) if preferred != build => {
+                    trace!(
+                        "{} is {:?}, looking for {:?}",
+                        path.display(),
+                        build,
+                        preferred
+                    );
+                    fallback.get_or_insert(candidate);
+                }
+                _ => return self.use_connection(candidate).await,
+            }
+        }
+
+        match fallback {
+            Some(candidate) => self.use_connection(candidate).await,
+            None => {
+                let why = last_error.unwrap_or(DiscordError::ConnectionClosed);
+                if let DiscordError::Closed { code, message } = &why {
+                    report_close(
+                        &mut self.event_handler_registry.clone(),
+                        &self.pending,
+                        &self.last_close,
+                        *code,
+                        message,
+                    );
+                }
+
+                Err(why)
+            }
+        }
+    }
+
+    async fn try_socket(&self, index: u8, path: &Path) -> Result<Candidate> {
+        let (mut read, mut write) = split(open(path).await?);
+        let mut reader = FrameReader::new(self.config.max_frame_size);
+
+        trace!("Performing handshake on {}", path.display());
+        write
+            .write_all(&handshake_message(self.client_id)?.encode()?)
+            .await?;
+        let msg = tokio::time::timeout(
+            self.config.command_timeout,
+            read_frame(&mut reader, &mut read),
+        )
+        .await
+        .map_err(|_| DiscordError::HandshakeTimeout)??;
+        let (data, build) = ready_data(&msg)?;
+
+        let info = ConnectionInfo {
+            index,
+            path: path.to_owned(),
+            build,
+        };
+
+        Ok((read, write, reader, info, data))
+    }
+
+    async fn use_connection(&self, (read, write, reader, info, ready): Candidate) -> Result<()> {
+        *self.writer.lock().await = Some(write);
+        // The close that ended the previous connection must not fail commands on this one
+        self.last_close.lock().take();
+        self.ready.store(true, Ordering::Release);
+
+        let subscriptions = self.subscriptions.lock().clone();
+        for (event, args) in subscriptions {
+            self.subscribe(event, &args).await;
+        }
+
+        self.event_handler_registry
+            .clone()
+            .handle(Event::Ready, ready)?;
+        trace!("Handshake completed");
+
+        trace!("Connected to {} ({:?})", info.path.display(), info.build);
+        *self.connection_info.write() = Some(info);
+
+        let task = tokio::spawn(self.clone().receive_loop(read, reader));
+        if let Some(previous) = self.reader_task.lock().replace(task) {
+            previous.abort();
+        }
+
+        Ok(())
+    }
+
+    async fn receive_loop(self, mut read: ReadHalf<IpcStream>, mut reader: FrameReader) {
+        let mut event_handler_registry = self.event_handler_registry.clone();
+        let mut heartbeat = Heartbeat::new(
+            self.config.heartbeat_interval,
+            self.config.heartbeat_timeout,
+            Instant::now(),
+        );
+
+        loop {
+            let frame = match heartbeat.next_poll(Instant::now()) {
+                Some(wait) => tokio::time::timeout(wait, read_frame(&mut reader, &mut read))
+                    .await
+                    .ok(),
+                None => Some(read_frame(&mut reader, &mut read).await),
+            };
+
+            match frame {
+                Some(Ok(msg)) => {
+                    heartbeat.received(Instant::now());
+                    match self.handle_frame(&mut event_handler_registry, msg).await {
+                        Err(DiscordError::Closed { code, message }) => {
+                            report_close(
+                                &mut event_handler_registry,
+                                &self.pending,
+                                &self.last_close,
+                                code,
+                                &message,
+                            );
+                            break;
+                        }
+                        Err(why) => trace!("discord error: {}", why),
+                        Ok(()) => {}
+                    }
+                }
+                Some(Err(DiscordError::FrameTooLarge { len, max })) => {
+                    trace!("Skipping frame of {} bytes, limit is {}", len, max);
+                }
+                Some(Err(why)) => {
+                    trace!("Connection lost: {}", why);
+                    break;
+                }
+                None => {}
+            }
+
+            let beat = match heartbeat.poll(Instant::now()) {
+                Beat::Ping => match ping_message() {
+                    Ok(ping) => self.send(&ping).await,
+                    Err(why) => Err(why),
+                },
+                Beat::Expired => Err(DiscordError::HeartbeatTimeout),
+                Beat::Idle => Ok(()),
+            };
+            if let Err(why) = beat {
+                trace!("Connection lost: {}", why);
+                break;
+            }
+        }
+
+        self.ready.store(false, Ordering::Release);
+        *self.connection_info.write() = None;
+        self.writer.lock().await.take();
+        self.pending.fail_all(|| DiscordError::ConnectionClosed);
+    }
+
+    async fn handle_frame(
+        &self,
+        event_handler_registry: &mut HandlerRegistry,
+        msg: Message,
+    ) -> Result<()> {
+        match msg.opcode {
+            OpCode::Ping => self.send(&pong_message(&msg)).await,
+            OpCode::Pong => Ok(()),
+            OpCode::Close => Err(close_error(&msg)),
+            _ => handle_inbound(event_handler_registry, &self.pending, msg),
+        }
+    }
+
+    /// Send a command and wait for the response carrying the same nonce
+    pub async fn execute(&self, nonce: String, message: Message) -> Result<JsonValue> {
+        let (tx, rx) = oneshot::channel();
+        self.pending.register_with(nonce.clone(), move |response| {
+            tx.send(response).ok();
+        });
+
+        if let Err(why) = self.send(&message).await {
+            self.pending.cancel(&nonce);
+            return Err(why);
+        }
+
+        match tokio::time::timeout(self.config.command_timeout, rx).await {
+            Ok(Ok(response)) => response,
+            Ok(Err(_)) => Err(DiscordError::ConnectionClosed),
+            Err(_) => {
+                self.pending.cancel(&nonce);
+                Err(RecvTimeoutError::Timeout.into())
+            }
+        }
+    }
+
+    async fn send(&self, message: &Message) -> Result<()> {
+        let bytes = message.encode()?;
+
+        match self.writer.lock().await.as_mut() {
+            Some(writer) => writer.write_all(&bytes).await?,
+            None => return Err(DiscordError::NotStarted),
+        }
+
+        trace!("-> {:?}", message);
+        Ok(())
+    }
+
+    /// Stop reading, send Discord a `Close` frame and close the socket
+    pub async fn disconnect(&self) {
+        if let Some(task) = self.reader_task.lock().take() {
+            task.abort();
+        }
+
+        if let Some(mut writer) = self.writer.lock().await.take() {
+            trace!("Closing connection");
+            let sent: Result<()> = async {
+                writer.write_all(&close_message()?.encode()?).await?;
+                Ok(())
+            }
+            .await;
+            if let Err(why) = sent {
+                trace!("Failed to send close frame: {}", why);
+            }
+            writer.shutdown().await.ok();
+        }
+
+        self.reset();
+    }
+
+    /// Stop reading and drop the socket without waiting, for when the client is dropped
+    ///
+    /// Unlike [`disconnect`](Self::disconnect) this sends no `Close` frame, as that would
+    /// have to be awaited.
+    pub fn abort(&self) {
+        if let Some(task) = self.reader_task.lock().take() {
+            task.abort();
+        }
+
+        // A send still holding the writer drops it along with the last clone of the manager
+        if let Ok(mut writer) = self.writer.try_lock() {
+            writer.take();
+        }
+
+        self.reset();
+    }
+
+    fn reset(&self) {
+        self.ready.store(false, Ordering::Release);
+        *self.connection_info.write() = None;
+        self.pending.fail_all(|| DiscordError::ConnectionClosed);
+    }
+}
+
+#[cfg(unix)]
+async fn open(path: &Path) -> Result<IpcStream> {
+    Ok(tokio::net::UnixStream::connect(path).await?)
+}
+
+#[cfg(windows)]
+async fn open(path: &Path) -> Result<IpcStream> {
+    Ok(tokio::net::windows::named_pipe::ClientOptions::new().open(path)?)
+}
+
+/// Read from `io` until a whole frame is available.
+async fn read_frame<R>(reader: &mut FrameReader, io: &mut R) -> Result<Message>
+where
+    R: AsyncRead + Unpin,
+{
+    let mut chunk = [0; READ_CHUNK_SIZE];
+
+    loop {
+        if let Some(msg) = reader.next_frame()? {
+            trace!("<- {:?}", msg);
+            return Ok(msg);
+        }
+
+        let n = io.read(&mut chunk).await?;
+        if n == 0 {
+            return Err(DiscordError::ConnectionClosed);
+        }
+
+        reader.extend(&chunk[..n]);
+    }
+}
@@ -1,110 +1,154 @@
+use super::{
+    reader::FrameReader,
+    record::{Direction, Recorder},
+};
 use crate::{
-    error::{DiscordError, Result},
+    error::{CloseCode, DiscordError, Result},
     models::message::{Message, OpCode},
     utils,
 };
-use bytes::BytesMut;
-use serde_json::json;
+use crossbeam_channel::Receiver;
+use serde_json::{json, Value as JsonValue};
 use std::{
     io::{Read, Write},
-    marker::Sized,
-    path::PathBuf,
-    thread, time,
+    thread,
+    time::{Duration, Instant},
 };
 
-/// Wait for a non-blocking connection until it's complete.
+/// How long to wait before retrying an operation the connection was not ready for
+const RETRY_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Wait for a non-blocking connection until it's complete, giving up at `deadline` or once
+/// something was sent to `stop`.
 macro_rules! try_until_done {
-    [ $e:expr ] => {
+    [ $e:expr, $deadline:expr, $stop:expr ] => {
         loop {
             match $e {
                 Ok(v) => break v,
                 Err(why) => if !why.io_would_block() { return Err(why); },
             }
 
-            thread::sleep(time::Duration::from_millis(500));
+            // Leave the stop request for the connection manager's loop to pick up
+            if !$stop.is_empty() {
+                return Err(DiscordError::ConnectionClosed);
+            }
+            if Instant::now() >= $deadline {
+                return Err(DiscordError::HandshakeTimeout);
+            }
+            thread::sleep(RETRY_INTERVAL);
         }
     }
 }
 
-pub trait Connection: Sized {
-    type Socket: Write + Read;
+/// A byte stream to Discord, such as a Unix socket, a named pipe or a TCP stream
+///
+/// Reads must not block for long. When nothing has arrived they should fail with
+/// [`std::io::ErrorKind::WouldBlock`] or [`std::io::ErrorKind::TimedOut`] so the
+/// connection manager can keep sending, and return `0` once the stream is closed.
+pub trait Connection: Read + Write + Send {}
 
-    /// The internally stored socket connection.
-    fn socket(&mut self) -> &mut Self::Socket;
+impl<T: Read + Write + Send> Connection for T {}
 
-    /// The base path were the socket is located.
-    fn ipc_path() -> PathBuf;
-
-    /// Establish a new connection to the server.
-    fn connect() -> Result<Self>;
-
-    /// The full socket path.
-    fn socket_path(n: u8) -> PathBuf {
-        let socket_path = format!("discord-ipc-{}", n);
-        let base_path = Self::ipc_path().join(socket_path.clone());
+/// A connection together with the reader splitting its bytes into frames.
+pub struct Framed {
+    stream: Box<dyn Connection>,
+    reader: FrameReader,
+    recorder: Option<Recorder>,
+}
 
-        if base_path.exists() {
-            base_path
-        } else {
-            // This fixes issues with Unix implementations
-            Self::ipc_path()
-                .join("app")
-                .join("com.discordapp.Discord")
-                .join(socket_path)
+impl Framed {
+    /// Wrap `stream`, rejecting frames larger than `max_frame_size` bytes.
+    pub fn new(stream: Box<dyn Connection>, max_frame_size: usize) -> Self {
+        Self {
+            stream,
+            reader: FrameReader::new(max_frame_size),
+            recorder: None,
         }
     }
 
-    /// Perform a handshake on this socket connection.
-    /// Will block until complete.
-    fn handshake(&mut self, client_id: u64) -> Result<Message> {
-        let hs = json![{
-            "client_id": client_id.to_string(),
-            "v": 1,
-            "nonce": utils::nonce()
-        }];
+    /// Write every frame sent or received from now on to `recorder`.
+    pub fn record_to(&mut self, recorder: Recorder) {
+        self.recorder = Some(recorder);
+    }
 
-        let msg = Message::new(OpCode::Handshake, hs)?;
-        try_until_done!(self.send(&msg));
-        let msg = try_until_done!(self.recv());
+    /// Perform a handshake on this connection.
+    /// Blocks until Discord answers, `timeout` passes or a stop request arrives on `stop`.
+    pub fn handshake(
+        &mut self,
+        client_id: u64,
+        timeout: Duration,
+        stop: &Receiver<()>,
+    ) -> Result<Message> {
+        let deadline = Instant::now() + timeout;
+        let msg = handshake_message(client_id)?;
+        try_until_done!(self.send(&msg), deadline, stop);
+        let msg = try_until_done!(self.recv(), deadline, stop);
 
         Ok(msg)
     }
 
-    /// Ping the server and get a pong response.
-    /// Will block until complete.
-    fn ping(&mut self) -> Result<OpCode> {
-        let message = Message::new(OpCode::Ping, json![{}])?;
-        try_until_done!(self.send(&message));
-        let response = try_until_done!(self.recv());
-        Ok(response.opcode)
-    }
-
     /// Send a message to the server.
-    fn send(&mut self, message: &Message) -> Result<()> {
+    pub fn send(&mut self, message: &Message) -> Result<()> {
         match message.encode() {
             Err(why) => error!("{:?}", why),
             Ok(bytes) => {
-                self.socket().write_all(&bytes)?;
+                self.stream.write_all(&bytes)?;
             }
         };
         trace!("-> {:?}", message);
+        if let Some(recorder) = &self.recorder {
+            recorder.record(Direction::Sent, message);
+        }
         Ok(())
     }
 
     /// Receive a message from the server.
-    fn recv(&mut self) -> Result<Message> {
-        let mut buf = BytesMut::new();
-        buf.resize(1024, 0);
-        let n = self.socket().read(&mut buf)?;
-        trace!("Received {} bytes", n);
-
-        if n == 0 {
-            return Err(DiscordError::ConnectionClosed);
-        }
-
-        let message = Message::decode(&buf[..n])?;
+    pub fn recv(&mut self) -> Result<Message> {
+        let message = self.reader.read_frame(&mut self.stream)?;
         trace!("<- {:?}", message);
+        if let Some(recorder) = &self.recorder {
+            recorder.record(Direction::Received, &message);
+        }
 
         Ok(message)
     }
 }
+
+/// The first message sent on a new connection.
+pub fn handshake_message(client_id: u64) -> Result<Message> {
+    let hs = json![{
+        "client_id": client_id.to_string(),
+        "v": 1,
+        "nonce": utils::nonce()
+    }];
+
+    Message::new(OpCode::Handshake, hs)
+}
+
+/// The error describing why Discord sent a `Close` frame.
+pub fn close_error(msg: &Message) -> DiscordError {
+    let payload: JsonValue = serde_json::from_str(&msg.payload).unwrap_or_default();
+
+    DiscordError::Closed {
+        code: payload["code"]
+            .as_u64()
+            .map_or(CloseCode::Normal, |code| CloseCode::from(code as u32)),
+        message: payload["message"].as_str().unwrap_or_default().to_owned(),
+    }
+}
+
+/// The payload of a `Close` frame.
+pub fn close_payload(code: CloseCode, message: &str) -> JsonValue {
+    json![{
+        "code": u32::from(code),
+        "message": message
+    }]
+}
+
+/// The frame sent to Discord before closing the connection on purpose.
+pub fn close_message() -> Result<Message> {
+    Message::new(
+        OpCode::Close,
+        close_payload(CloseCode::Normal, "Client shutting down"),
+    )
+}
@@ -0,0 +1,132 @@
+use super::{paths, platform, socket_order, Connection};
+use crate::Result;
+use std::{
+    fmt::Debug,
+    net::TcpStream,
+    path::{Path, PathBuf},
+};
+
+/// A place the client can try to reach Discord at
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Endpoint {
+    /// The `N` in `discord-ipc-N`
+    pub index: u8,
+    /// Where the endpoint lives, e.g. the path of the socket
+    pub path: PathBuf,
+}
+
+/// Opens the connections the client talks to Discord over
+///
+/// The default, [`IpcConnector`], looks for Discord's local sockets. Implement this to run
+/// the client over another transport, e.g. an in-memory pipe in tests.
+pub trait Connector: Debug + Send + Sync {
+    /// The endpoints to try, in order
+    ///
+    /// `preferred_socket` and `ipc_dirs` are the options set on the
+    /// [`ClientBuilder`](crate::ClientBuilder); connectors are free to ignore them.
+    fn endpoints(&self, preferred_socket: Option<u8>, ipc_dirs: &[PathBuf]) -> Vec<Endpoint>;
+
+    /// Open a connection to `endpoint`
+    fn connect(&self, endpoint: &Endpoint) -> Result<Box<dyn Connection>>;
+}
+
+/// Connects to the `discord-ipc-N` sockets of a locally running Discord client
+#[derive(Debug, Clone, Copy, Default)]
+pub struct IpcConnector;
+
+impl Connector for IpcConnector {
+    fn endpoints(&self, preferred_socket: Option<u8>, ipc_dirs: &[PathBuf]) -> Vec<Endpoint> {
+        let dirs = paths::candidate_dirs(ipc_dirs, platform::ipc_dirs());
+
+        socket_order(preferred_socket)
+            .into_iter()
+            .flat_map(|index| {
+                dirs.iter().map(move |dir| Endpoint {
+                    index,
+                    path: paths::socket_path(dir, index),
+                })
+            })
+            .collect()
+    }
+
+    fn connect(&self, endpoint: &Endpoint) -> Result<Box<dyn Connection>> {
+        Ok(Box::new(platform::connect(&endpoint.path)?))
+    }
+}
+
+/// Connects to a TCP bridge forwarding to Discord's IPC socket, e.g. a proxy in a container
+///
+/// The bridge must pass the IPC frames through unchanged.
+#[derive(Debug, Clone)]
+pub struct TcpConnector {
+    addr: String,
+}
+
+impl TcpConnector {
+    /// Connect to `addr`, given as `host:port`
+    pub fn new(addr: impl Into<String>) -> Self {
+        Self { addr: addr.into() }
+    }
+}
+
+impl Connector for TcpConnector {
+    fn endpoints(&self, _preferred_socket: Option<u8>, _ipc_dirs: &[PathBuf]) -> Vec<Endpoint> {
+        vec![Endpoint {
+            index: 0,
+            path: PathBuf::from(&self.addr),
+        }]
+    }
+
+    fn connect(&self, endpoint: &Endpoint) -> Result<Box<dyn Connection>> {
+        let stream = TcpStream::connect(path_str(&endpoint.path))?;
+        stream.set_nodelay(true)?;
+        stream.set_nonblocking(true)?;
+
+        Ok(Box::new(stream))
+    }
+}
+
+fn path_str(path: &Path) -> &str {
+    path.to_str().unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tries_every_socket_in_every_dir() {
+        let dirs = [PathBuf::from("/first"), PathBuf::from("/second")];
+        let endpoints = IpcConnector.endpoints(Some(2), &dirs);
+
+        assert_eq!(
+            endpoints[..2],
+            [
+                Endpoint {
+                    index: 2,
+                    path: PathBuf::from("/first/discord-ipc-2"),
+                },
+                Endpoint {
+                    index: 2,
+                    path: PathBuf::from("/second/discord-ipc-2"),
+                },
+            ]
+        );
+        assert!(endpoints
+            .iter()
+            .any(|endpoint| endpoint.path == Path::new("/second/discord-ipc-9")));
+    }
+
+    #[test]
+    fn tcp_has_a_single_endpoint() {
+        let endpoints = TcpConnector::new("127.0.0.1:6463").endpoints(Some(3), &[]);
+
+        assert_eq!(
+            endpoints,
+            vec![Endpoint {
+                index: 0,
+                path: PathBuf::from("127.0.0.1:6463"),
+            }]
+        );
+    }
+}
@@ -0,0 +1,143 @@
+use crate::{
+    error::Result,
+    models::message::{Message, OpCode},
+    utils,
+};
+use serde_json::json;
+use std::time::{Duration, Instant};
+
+/// How long the connection may stay quiet before a `Ping` is sent, unless configured otherwise
+pub const DEFAULT_HEARTBEAT_INTERVAL: Duration = Duration::from_secs(30);
+
+/// How long to wait for the `Pong` before the connection is considered dead
+pub const DEFAULT_HEARTBEAT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// What the connection should do to keep the heartbeat going
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Beat {
+    /// Nothing to do yet
+    Idle,
+    /// Send a `Ping` now
+    Ping,
+    /// The last `Ping` went unanswered
+    Expired,
+}
+
+/// Tracks when Discord was last heard from and when to ping it
+///
+/// Any frame received counts as a sign of life, so a busy connection is never pinged.
+#[derive(Debug, Clone)]
+pub struct Heartbeat {
+    interval: Option<Duration>,
+    timeout: Duration,
+    last_received: Instant,
+    ping_sent: Option<Instant>,
+}
+
+impl Heartbeat {
+    /// Start tracking a connection that was just established; `None` disables pings
+    pub fn new(interval: Option<Duration>, timeout: Duration, now: Instant) -> Self {
+        Self {
+            interval,
+            timeout,
+            last_received: now,
+            ping_sent: None,
+        }
+    }
+
+    /// Record that a frame was received
+    pub fn received(&mut self, now: Instant) {
+        self.last_received = now;
+        self.ping_sent = None;
+    }
+
+    /// Decide what to do at `now`, remembering a `Ping` as sent if one is due
+    pub fn poll(&mut self, now: Instant) -> Beat {
+        let interval = match self.interval {
+            Some(interval) => interval,
+            None => return Beat::Idle,
+        };
+
+        match self.ping_sent {
+            Some(sent) if now.saturating_duration_since(sent) >= self.timeout => Beat::Expired,
+            Some(_) => Beat::Idle,
+            None if now.saturating_duration_since(self.last_received) >= interval => {
+                self.ping_sent = Some(now);
+                Beat::Ping
+            }
+            None => Beat::Idle,
+        }
+    }
+
+    /// How long until [`poll`](Self::poll) has something to do
+    #[cfg(feature = "tokio")]
+    pub fn next_poll(&self, now: Instant) -> Option<Duration> {
+        let deadline = match self.ping_sent {
+            Some(sent) => sent + self.timeout,
+            None => self.last_received + self.interval?,
+        };
+
+        Some(deadline.saturating_duration_since(now))
+    }
+}
+
+/// A `Ping` frame for the heartbeat
+pub fn ping_message() -> Result<Message> {
+    Message::new(OpCode::Ping, json!({ "nonce": utils::nonce() }))
+}
+
+/// The `Pong` answering `ping`, echoing its payload
+pub fn pong_message(ping: &Message) -> Message {
+    Message {
+        opcode: OpCode::Pong,
+        payload: ping.payload.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INTERVAL: Duration = Duration::from_secs(30);
+    const TIMEOUT: Duration = Duration::from_secs(10);
+
+    #[test]
+    fn pings_quiet_connections() {
+        let start = Instant::now();
+        let mut heartbeat = Heartbeat::new(Some(INTERVAL), TIMEOUT, start);
+
+        assert_eq!(heartbeat.poll(start + Duration::from_secs(29)), Beat::Idle);
+        assert_eq!(heartbeat.poll(start + INTERVAL), Beat::Ping);
+        assert_eq!(heartbeat.poll(start + Duration::from_secs(35)), Beat::Idle);
+
+        heartbeat.received(start + Duration::from_secs(36));
+        assert_eq!(heartbeat.poll(start + Duration::from_secs(50)), Beat::Idle);
+        assert_eq!(heartbeat.poll(start + Duration::from_secs(66)), Beat::Ping);
+    }
+
+    #[test]
+    fn expires_without_pong() {
+        let start = Instant::now();
+        let mut heartbeat = Heartbeat::new(Some(INTERVAL), TIMEOUT, start);
+
+        assert_eq!(heartbeat.poll(start + INTERVAL), Beat::Ping);
+        assert_eq!(heartbeat.poll(start + INTERVAL + TIMEOUT), Beat::Expired);
+    }
+
+    #[test]
+    fn disabled_heartbeat_never_pings() {
+        let start = Instant::now();
+        let mut heartbeat = Heartbeat::new(None, TIMEOUT, start);
+
+        assert_eq!(heartbeat.poll(start + INTERVAL * 100), Beat::Idle);
+    }
+
+    #[test]
+    fn pong_echoes_ping_payload() {
+        let ping = ping_message().unwrap();
+        let pong = pong_message(&ping);
+
+        assert_eq!(pong.opcode, OpCode::Pong);
+        assert_eq!(pong.payload, ping.payload);
+    }
+}
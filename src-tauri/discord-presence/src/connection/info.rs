@@ -0,0 +1,92 @@
+use crate::models::ReadyEvent;
+use std::path::PathBuf;
+
+/// The Discord release channel on the other end of an IPC socket
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DiscordBuild {
+    /// Discord Stable
+    Stable,
+    /// Discord Public Test Build
+    Ptb,
+    /// Discord Canary
+    Canary,
+    /// A client which reported an API endpoint we do not recognize
+    Other,
+}
+
+impl DiscordBuild {
+    /// Guess the build from the `api_endpoint` sent in the `READY` payload
+    pub fn from_api_endpoint(endpoint: &str) -> Self {
+        let host = endpoint
+            .trim_start_matches("https:")
+            .trim_start_matches("//");
+
+        if host.starts_with("ptb.") {
+            Self::Ptb
+        } else if host.starts_with("canary.") {
+            Self::Canary
+        } else if host.starts_with("discord.com") || host.starts_with("discordapp.com") {
+            Self::Stable
+        } else {
+            Self::Other
+        }
+    }
+
+    /// Guess the build from a `READY` payload
+    pub fn from_ready(ready: &ReadyEvent) -> Self {
+        ready
+            .config
+            .as_ref()
+            .and_then(|config| config.api_endpoint.as_deref())
+            .map_or(Self::Other, Self::from_api_endpoint)
+    }
+}
+
+/// Details about the socket the client ended up connecting to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConnectionInfo {
+    /// The `N` in `discord-ipc-N`
+    pub index: u8,
+    /// The full path of the socket
+    pub path: PathBuf,
+    /// The Discord build listening on that socket
+    pub build: DiscordBuild,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_build_from_endpoint() {
+        assert_eq!(
+            DiscordBuild::from_api_endpoint("//discord.com/api"),
+            DiscordBuild::Stable
+        );
+        assert_eq!(
+            DiscordBuild::from_api_endpoint("//ptb.discord.com/api"),
+            DiscordBuild::Ptb
+        );
+        assert_eq!(
+            DiscordBuild::from_api_endpoint("https://canary.discord.com/api"),
+            DiscordBuild::Canary
+        );
+        assert_eq!(
+            DiscordBuild::from_api_endpoint("//localhost:3000/api"),
+            DiscordBuild::Other
+        );
+    }
+
+    #[test]
+    fn detects_build_from_ready() {
+        let ready: ReadyEvent =
+            serde_json::from_str(r#"{"v":1,"config":{"api_endpoint":"//canary.discord.com/api"}}"#)
+                .unwrap();
+
+        assert_eq!(DiscordBuild::from_ready(&ready), DiscordBuild::Canary);
+        assert_eq!(
+            DiscordBuild::from_ready(&ReadyEvent::new()),
+            DiscordBuild::Other
+        );
+    }
+}
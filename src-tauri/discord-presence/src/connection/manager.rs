@@ -1,59 +1,222 @@
-use super::{Connection, SocketConnection};
+use super::{
+    base::{close_error, close_message, Framed},
+    heartbeat::{ping_message, pong_message, Beat, Heartbeat},
+    pending::PendingRequests,
+    rate_limit::{Clock, RateLimit, RateLimiter, SystemClock},
+    record::Recorder,
+    ConnectionInfo, Connector, DiscordBuild, Endpoint, IpcConnector, ReconnectPolicy,
+    DEFAULT_HEARTBEAT_INTERVAL, DEFAULT_HEARTBEAT_TIMEOUT, DEFAULT_MAX_FRAME_SIZE,
+};
 use crate::{
-    error::{DiscordError, Result},
-    event_handler::HandlerRegistry,
-    models::{payload::Payload, Event, Message},
+    error::{CloseCode, DiscordError, Result},
+    event_handler::{HandlerExecutor, HandlerRegistry, ThreadExecutor},
+    models::{
+        payload::Payload, Activity, Command, Event, Message, OpCode, ReadyEvent, SetActivityArgs,
+        SubscriptionArgs, ValidationMode,
+    },
 };
-use crossbeam_channel::{unbounded, Receiver, Sender};
-use parking_lot::Mutex;
-use serde_json::Value as JsonValue;
+use crossbeam_channel::{bounded, unbounded, Receiver, RecvTimeoutError, Sender};
+use parking_lot::{Mutex, RwLock};
+use serde::Deserialize;
+use serde_json::{json, Value as JsonValue};
 use std::{
-    io::ErrorKind,
-    sync::{atomic::Ordering, Arc},
-    thread, time,
+    collections::HashMap,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
 };
 
 type Tx = Sender<Message>;
 type Rx = Receiver<Message>;
 
+/// Why Discord last closed the connection, kept until the next command picks it up or a new
+/// connection is established
+pub type LastClose = Arc<Mutex<Option<(CloseCode, String)>>>;
+
+/// The events to subscribe to on every connection, with the arguments to subscribe with
+pub type Subscriptions = Arc<Mutex<HashMap<Event, SubscriptionArgs>>>;
+
+/// A `SET_ACTIVITY` command waiting for the rate limit
+#[derive(Debug)]
+struct QueuedActivity {
+    nonce: String,
+    message: Message,
+    activity: Activity,
+}
+
+/// The activity asked for, the one Discord shows and the one waiting to replace it
+#[derive(Debug)]
+struct Activities {
+    requested: Mutex<Option<Activity>>,
+    queue: Mutex<RateLimiter<QueuedActivity>>,
+    applied: Mutex<Option<Activity>>,
+}
+
+/// How long the IO thread waits for outbound messages before polling the socket again
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// How long commands wait for Discord's response unless configured otherwise
+pub const DEFAULT_COMMAND_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Options controlling how the manager picks and talks to a Discord socket
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Socket index to try before all others
+    pub preferred_socket: Option<u8>,
+    /// Discord build to prefer when several are running
+    pub preferred_build: Option<DiscordBuild>,
+    /// Directories searched before the platform defaults
+    pub ipc_dirs: Vec<PathBuf>,
+    /// Largest frame payload accepted from Discord, in bytes
+    pub max_frame_size: usize,
+    /// How long a command or the handshake waits for Discord's response
+    pub command_timeout: Duration,
+    /// When to retry failed or dropped connections
+    pub reconnect: ReconnectPolicy,
+    /// How long the connection may stay quiet before Discord is pinged, `None` to never ping
+    pub heartbeat_interval: Option<Duration>,
+    /// How long to wait for the `Pong` before reconnecting
+    pub heartbeat_timeout: Duration,
+    /// Opens the connections to Discord
+    pub connector: Arc<dyn Connector>,
+    /// Where to record the frames exchanged with Discord
+    pub recorder: Option<Recorder>,
+    /// What to do with activities that break Discord's limits
+    pub activity_validation: ValidationMode,
+    /// Runs the event handlers
+    pub handler_executor: Arc<dyn HandlerExecutor>,
+    /// How often queued activities may be sent
+    pub activity_rate_limit: RateLimit,
+    /// How far timestamps may drift for an activity to count as unchanged, `None` to send
+    /// every activity
+    pub duplicate_activity_tolerance: Option<Duration>,
+    /// Whether to set the last activity again after every handshake
+    pub restore_activity: bool,
+    /// Tells the time for the activity rate limit
+    pub clock: Arc<dyn Clock>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            preferred_socket: None,
+            preferred_build: None,
+            ipc_dirs: Vec::new(),
+            max_frame_size: DEFAULT_MAX_FRAME_SIZE,
+            command_timeout: DEFAULT_COMMAND_TIMEOUT,
+            reconnect: ReconnectPolicy::default(),
+            heartbeat_interval: Some(DEFAULT_HEARTBEAT_INTERVAL),
+            heartbeat_timeout: DEFAULT_HEARTBEAT_TIMEOUT,
+            connector: Arc::new(IpcConnector),
+            recorder: None,
+            activity_validation: ValidationMode::Off,
+            handler_executor: Arc::new(ThreadExecutor::default()),
+            activity_rate_limit: RateLimit::default(),
+            duplicate_activity_tolerance: Some(Duration::ZERO),
+            restore_activity: true,
+            clock: Arc::new(SystemClock),
+        }
+    }
+}
+
 // TODO: Refactor connection manager
 #[derive(Clone)]
 pub struct Manager {
-    connection: Arc<Option<Mutex<SocketConnection>>>,
+    connection: Arc<Option<Mutex<Framed>>>,
+    connection_info: Arc<RwLock<Option<ConnectionInfo>>>,
     client_id: u64,
+    config: Config,
     outbound: (Rx, Tx),
     inbound: (Rx, Tx),
+    pending: PendingRequests,
+    last_close: LastClose,
+    subscriptions: Subscriptions,
+    activities: Arc<Activities>,
+    started: Arc<AtomicBool>,
+    ready: Arc<AtomicBool>,
     handshake_completed: bool,
-    stop: bool,
-    event_handler_registry: HandlerRegistry<'static>,
+    stop: (Sender<()>, Receiver<()>),
+    thread: Arc<Mutex<Option<JoinHandle<()>>>>,
+    event_handler_registry: HandlerRegistry,
 }
 
 impl Manager {
-    pub fn new(client_id: u64, event_handler_registry: HandlerRegistry<'static>) -> Self {
+    pub fn new(client_id: u64, config: Config, event_handler_registry: HandlerRegistry) -> Self {
         let connection = Arc::new(None);
         let (sender_o, receiver_o) = unbounded();
         let (sender_i, receiver_i) = unbounded();
+        let activity_queue = RateLimiter::new(config.activity_rate_limit, config.clock.clone());
 
         Self {
             connection,
+            connection_info: Arc::new(RwLock::new(None)),
             client_id,
-            stop: false,
+            config,
+            stop: bounded(1),
+            thread: Arc::new(Mutex::new(None)),
             handshake_completed: false,
             inbound: (receiver_i, sender_i),
             outbound: (receiver_o, sender_o),
+            pending: PendingRequests::default(),
+            last_close: Arc::new(Mutex::new(None)),
+            subscriptions: Arc::new(Mutex::new(HashMap::new())),
+            activities: Arc::new(Activities {
+                requested: Mutex::new(None),
+                queue: Mutex::new(activity_queue),
+                applied: Mutex::new(None),
+            }),
+            started: Arc::new(AtomicBool::new(false)),
+            ready: Arc::new(AtomicBool::new(false)),
             event_handler_registry,
         }
     }
 
-    pub fn start(&mut self) -> std::thread::JoinHandle<()> {
+    /// Spawn the IO thread, unless it is already running
+    pub fn start(&mut self) {
+        let mut thread = self.thread.lock();
+        if thread.is_some() && self.is_started() {
+            return;
+        }
+        // The previous thread gave up on connecting and is about to exit
+        if let Some(finished) = thread.take() {
+            finished.join().ok();
+        }
+
+        // A stop request left over from an earlier shutdown must not end the new thread
+        while self.stop.1.try_recv().is_ok() {}
+        self.started.store(true, Ordering::Release);
+
         let manager_inner = self.clone();
-        thread::spawn(move || {
+        *thread = Some(thread::spawn(move || {
             send_and_receive_loop(manager_inner);
-        })
+        }));
+    }
+
+    /// Tell the IO thread to close the connection and wait until it has exited
+    ///
+    /// Does not wait when called from the IO thread itself, e.g. inside an event handler run
+    /// by an [`InlineExecutor`](crate::InlineExecutor).
+    pub fn shutdown(&self) {
+        self.stop.0.try_send(()).ok();
+
+        let thread = self.thread.lock().take();
+        if let Some(thread) = thread {
+            if thread.thread().id() != thread::current().id() {
+                thread.join().ok();
+            }
+        }
     }
 
-    pub fn stop(&mut self) {
-        self.stop = true;
+    /// Block until the IO thread exits, either after a shutdown or after giving up on connecting
+    pub fn join(&self) {
+        let thread = self.thread.lock().take();
+        if let Some(thread) = thread {
+            thread.join().ok();
+        }
     }
 
     pub fn send(&self, message: Message) -> Result<()> {
@@ -62,6 +225,168 @@ impl Manager {
         Ok(())
     }
 
+    /// Send a command and wait for the response carrying the same nonce
+    ///
+    /// Gives up after `timeout`, or after the configured command timeout if it is `None`.
+    pub fn execute(
+        &self,
+        nonce: String,
+        message: Message,
+        timeout: Option<Duration>,
+    ) -> Result<JsonValue> {
+        self.execute_with(nonce, timeout, || self.send(message))
+    }
+
+    /// [`execute`](Self::execute) for an activity set without going through the queue
+    ///
+    /// Once the frame is on its way it counts against the rate limit and drops the queued
+    /// activity, which is older. Commands failing before that leave both untouched.
+    pub fn execute_activity(
+        &self,
+        nonce: String,
+        message: Message,
+        timeout: Option<Duration>,
+    ) -> Result<JsonValue> {
+        self.execute_with(nonce, timeout, || {
+            // Held until the frame is queued, so the IO thread cannot send the older one after it
+            let mut queue = self.activities.queue.lock();
+            self.send(message)?;
+            queue.bypass();
+            Ok(())
+        })
+    }
+
+    fn execute_with<F>(
+        &self,
+        nonce: String,
+        timeout: Option<Duration>,
+        send: F,
+    ) -> Result<JsonValue>
+    where
+        F: FnOnce() -> Result<()>,
+    {
+        let response = self.pending.register(nonce.clone());
+
+        if let Err(why) = send() {
+            self.pending.cancel(&nonce);
+            return Err(why);
+        }
+
+        let result = response.recv_timeout(timeout.unwrap_or(self.config.command_timeout));
+        self.pending.cancel(&nonce);
+
+        result?
+    }
+
+    /// Subscribe to `event` on this and every later connection, unless already subscribed
+    ///
+    /// Does not wait for Discord's answer, so it is safe to call from an event handler.
+    pub fn ensure_subscribed(&self, event: Event) -> Result<()> {
+        let args = SubscriptionArgs::default();
+        {
+            let mut subscriptions = self.subscriptions.lock();
+            if subscriptions.contains_key(&event) {
+                return Ok(());
+            }
+            subscriptions.insert(event, args.clone());
+        }
+
+        // Not yet connected, the subscription is sent once the handshake completes
+        if !self.is_ready() {
+            return Ok(());
+        }
+
+        let (nonce, message) = subscribe_message(event, &args)?;
+        self.pending
+            .register_with(nonce, move |response| log_refusal(event, response));
+        self.send(message)
+    }
+
+    /// Set `activity` once the rate limit allows, replacing any activity still queued
+    ///
+    /// Does not wait for Discord's answer, refusals are logged.
+    pub fn queue_activity(&self, activity: Activity) -> Result<()> {
+        self.request_activity(Some(activity.clone()));
+        self.enqueue_activity(activity)
+    }
+
+    fn enqueue_activity(&self, activity: Activity) -> Result<()> {
+        let payload = Payload::with_nonce(
+            Command::SetActivity,
+            Some(SetActivityArgs::new(|_| activity.clone())),
+            None,
+            None,
+        );
+        let nonce = into_error!(payload.nonce.clone())?;
+        let message = Message::new(OpCode::Frame, payload)?;
+        self.activities.queue.lock().queue(QueuedActivity {
+            nonce,
+            message,
+            activity,
+        });
+
+        // Not yet connected, the IO thread sends it once the handshake completes
+        if !self.is_ready() {
+            return Ok(());
+        }
+
+        match due_activity(&self.activities, &self.pending) {
+            Some(message) => self.send(message),
+            None => Ok(()),
+        }
+    }
+
+    /// Remember the activity last asked for, to set it again after reconnecting
+    ///
+    /// `None` once the activity was cleared, as Discord starts every connection without one.
+    pub fn request_activity(&self, activity: Option<Activity>) {
+        *self.activities.requested.lock() = activity;
+    }
+
+    /// Whether Discord already shows `activity`, give or take the configured tolerance
+    ///
+    /// Drops the queued activity if so, which would otherwise replace it.
+    pub fn already_applied(&self, activity: &Activity) -> bool {
+        let tolerance = match self.config.duplicate_activity_tolerance {
+            Some(tolerance) => tolerance,
+            None => return false,
+        };
+
+        let unchanged = self
+            .activities
+            .applied
+            .lock()
+            .as_ref()
+            .is_some_and(|applied| applied.matches(activity, tolerance));
+        if unchanged {
+            self.activities.queue.lock().discard();
+        }
+
+        unchanged
+    }
+
+    /// Remember the activity Discord accepted, `None` once it was cleared
+    pub fn activity_applied(&self, activity: Option<Activity>) {
+        *self.activities.applied.lock() = activity;
+    }
+
+    /// Renew the subscription to `event` with `args` whenever the client reconnects
+    pub fn keep_subscription(&self, event: Event, args: SubscriptionArgs) {
+        self.subscriptions.lock().insert(event, args);
+    }
+
+    /// Stop renewing the subscription to `event`
+    pub fn forget_subscription(&self, event: Event) {
+        self.subscriptions.lock().remove(&event);
+    }
+
+    /// The reason Discord gave for closing the connection, if it did since the last call and
+    /// no new connection has been established since
+    pub fn take_close_error(&self) -> Option<DiscordError> {
+        take_close_error(&self.last_close)
+    }
+
+    #[allow(dead_code)]
     pub fn recv(&self) -> Result<Message> {
         self.inbound.0.recv().map_err(DiscordError::from)
     }
@@ -70,6 +395,28 @@ impl Manager {
         self.client_id
     }
 
+    pub fn connection_info(&self) -> Option<ConnectionInfo> {
+        self.connection_info.read().clone()
+    }
+
+    /// Whether the IO thread is running and has not given up on connecting
+    pub fn is_started(&self) -> bool {
+        self.started.load(Ordering::Acquire)
+    }
+
+    /// Whether the handshake with Discord has completed on the current connection
+    pub fn is_ready(&self) -> bool {
+        self.ready.load(Ordering::Acquire)
+    }
+
+    fn heartbeat(&self) -> Heartbeat {
+        Heartbeat::new(
+            self.config.heartbeat_interval,
+            self.config.heartbeat_timeout,
+            Instant::now(),
+        )
+    }
+
     fn connect(&mut self) -> Result<()> {
         if self.connection.is_some() {
             return Ok(());
@@ -77,37 +424,232 @@ impl Manager {
 
         trace!("Connecting");
 
-        let mut new_connection = SocketConnection::connect()?;
+        let connector = self.config.connector.clone();
+        let endpoints = connector.endpoints(self.config.preferred_socket, &self.config.ipc_dirs);
+        let mut fallback = None;
+        let mut last_error = None;
 
-        trace!("Performing handshake");
-        let msg = new_connection.handshake(self.client_id)?;
-        let payload: Payload<JsonValue> = serde_json::from_str(&msg.payload)?;
-        self.event_handler_registry
-            .handle(Event::Ready, into_error!(payload.data)?)?;
-        trace!("Handshake completed");
+        for endpoint in endpoints {
+            let path = endpoint.path.clone();
+            trace!("Probing {}", path.display());
 
-        self.connection = Arc::new(Some(Mutex::new(new_connection)));
+            let candidate = match self.try_endpoint(&*connector, endpoint) {
+                Ok(candidate) => candidate,
+                Err(why) => {
+                    trace!("Skipping {}: {}", path.display(), why);
+                    keep_error(&mut last_error, why);
+                    continue;
+                }
+            };
 
-        trace!("Connected");
+            let build = candidate.1.build;
+            match self.config.preferred_build {
+                Some(preferred) if preferred != build => {
+                    trace!(
+                        "{} is {:?}, looking for {:?}",
+                        path.display(),
+                        build,
+                        preferred
+                    );
+                    fallback.get_or_insert(candidate);
+                }
+                _ => return self.use_connection(candidate),
+            }
+        }
+
+        match fallback {
+            Some(candidate) => self.use_connection(candidate),
+            None => Err(last_error.unwrap_or(DiscordError::ConnectionClosed)),
+        }
+    }
+
+    /// Connect to a single endpoint and perform the handshake on it.
+    fn try_endpoint(
+        &self,
+        connector: &dyn Connector,
+        endpoint: Endpoint,
+    ) -> Result<(Framed, ConnectionInfo, JsonValue)> {
+        let stream = connector.connect(&endpoint)?;
+        let mut connection = Framed::new(stream, self.config.max_frame_size);
+        if let Some(recorder) = &self.config.recorder {
+            connection.record_to(recorder.clone());
+        }
+
+        trace!("Performing handshake on {}", endpoint.path.display());
+        let msg =
+            connection.handshake(self.client_id, self.config.command_timeout, &self.stop.1)?;
+        let (data, build) = ready_data(&msg)?;
+
+        let info = ConnectionInfo {
+            index: endpoint.index,
+            path: endpoint.path,
+            build,
+        };
+
+        Ok((connection, info, data))
+    }
+
+    fn use_connection(
+        &mut self,
+        (mut connection, info, ready): (Framed, ConnectionInfo, JsonValue),
+    ) -> Result<()> {
+        // The close that ended the previous connection must not fail commands on this one
+        self.last_close.lock().take();
+        self.ready.store(true, Ordering::Release);
+        self.subscribe_all(&mut connection);
+        if self.config.restore_activity {
+            self.restore_activity();
+        }
+        self.event_handler_registry.handle(Event::Ready, ready)?;
+        trace!("Handshake completed");
+
+        self.connection = Arc::new(Some(Mutex::new(connection)));
+        trace!("Connected to {} ({:?})", info.path.display(), info.build);
+        *self.connection_info.write() = Some(info);
 
         Ok(())
     }
 
+    /// Send the subscriptions kept so far on a fresh connection.
+    fn subscribe_all(&self, connection: &mut Framed) {
+        let subscriptions = self.subscriptions.lock().clone();
+
+        for (event, args) in subscriptions {
+            let result = subscribe_message(event, &args).and_then(|(nonce, message)| {
+                self.pending
+                    .register_with(nonce, move |response| log_refusal(event, response));
+                connection.send(&message)
+            });
+
+            if let Err(why) = result {
+                error!("Failed to subscribe to {:?}: {}", event, why);
+            }
+        }
+    }
+
+    /// Queue the activity last asked for, which a fresh connection does not show yet.
+    fn restore_activity(&self) {
+        let requested = self.activities.requested.lock().clone();
+        if let Some(activity) = requested {
+            trace!("Restoring the last activity");
+            if let Err(why) = self.enqueue_activity(activity) {
+                error!("Failed to restore the last activity: {}", why);
+            }
+        }
+    }
+
     fn disconnect(&mut self) {
+        trace!("Disconnected");
+        self.ready.store(false, Ordering::Release);
+
+        // Commands queued for the old connection have already been failed
+        while self.outbound.0.try_recv().is_ok() {}
+        self.pending.fail_all(|| DiscordError::ConnectionClosed);
         self.handshake_completed = false;
         self.connection = Arc::new(None);
+        *self.connection_info.write() = None;
+        // Discord drops the activity along with the connection
+        self.activity_applied(None);
+    }
+}
+
+/// Remember `why` as the reason connecting failed, unless Discord already gave a better one.
+pub fn keep_error(last_error: &mut Option<DiscordError>, why: DiscordError) {
+    if !matches!(last_error, Some(DiscordError::Closed { .. })) {
+        *last_error = Some(why);
+    }
+}
+
+/// Fail outstanding commands with the reason Discord closed the connection, keep it for
+/// the next command and pass it to the error handlers.
+pub fn report_close(
+    event_handler_registry: &mut HandlerRegistry,
+    pending: &PendingRequests,
+    last_close: &LastClose,
+    code: CloseCode,
+    message: &str,
+) {
+    error!("Discord closed the connection: {:?} {}", code, message);
+
+    pending.fail_all(|| DiscordError::Closed {
+        code,
+        message: message.to_owned(),
+    });
+    *last_close.lock() = Some((code, message.to_owned()));
+
+    let data = json!({ "code": u32::from(code), "message": message });
+    if let Err(why) = event_handler_registry.handle(Event::Error, data) {
+        trace!("discord error: {}", why);
     }
 }
 
+/// A `SUBSCRIBE` command for `event` and the nonce its answer will carry.
+pub fn subscribe_message(event: Event, args: &SubscriptionArgs) -> Result<(String, Message)> {
+    let payload = Payload::with_nonce(Command::Subscribe, Some(args), None, Some(event));
+    let nonce = into_error!(payload.nonce.clone())?;
+
+    Ok((nonce, Message::new(OpCode::Frame, payload)?))
+}
+
+/// Log Discord's answer to a subscription nobody waits for if it was refused.
+pub fn log_refusal(event: Event, response: Result<JsonValue>) {
+    if let Err(why) = response {
+        error!("Failed to subscribe to {:?}: {}", event, why);
+    }
+}
+
+/// Take the queued activity if the rate limit allows sending it, remembering it once
+/// Discord applied it.
+fn due_activity(activities: &Arc<Activities>, pending: &PendingRequests) -> Option<Message> {
+    let QueuedActivity {
+        nonce,
+        message,
+        activity,
+    } = activities.queue.lock().poll()?;
+
+    let activities = activities.clone();
+    pending.register_with(nonce, move |response| match response {
+        Ok(_) => *activities.applied.lock() = Some(activity),
+        Err(why) => error!("Failed to set the queued activity: {}", why),
+    });
+
+    Some(message)
+}
+
+/// Turn a reason kept by [`report_close`] into an error, clearing it.
+pub fn take_close_error(last_close: &LastClose) -> Option<DiscordError> {
+    last_close
+        .lock()
+        .take()
+        .map(|(code, message)| DiscordError::Closed { code, message })
+}
+
+/// Extract the `READY` data and the Discord build from the handshake response.
+///
+/// Fails with [`DiscordError::Closed`] if Discord rejected the handshake.
+pub fn ready_data(msg: &Message) -> Result<(JsonValue, DiscordBuild)> {
+    if msg.opcode == OpCode::Close {
+        return Err(close_error(msg));
+    }
+
+    let payload: Payload<JsonValue> = serde_json::from_str(&msg.payload)?;
+    let data = into_error!(payload.data)?;
+    let ready: ReadyEvent = serde_json::from_value(data.clone())?;
+
+    Ok((data, DiscordBuild::from_ready(&ready)))
+}
+
 fn send_and_receive_loop(mut manager: Manager) {
     trace!("Starting sender loop");
 
     let mut inbound = manager.inbound.1.clone();
     let outbound = manager.outbound.0.clone();
+    let mut attempt = 0;
+    let mut heartbeat = manager.heartbeat();
 
     loop {
         let connection = manager.connection.clone();
-        if manager.stop {
+        if manager.stop.1.try_recv().is_ok() {
             break;
         }
 
@@ -117,47 +659,571 @@ fn send_and_receive_loop(mut manager: Manager) {
                 match send_and_receive(
                     &mut connection,
                     &mut manager.event_handler_registry,
+                    &manager.pending,
                     &mut inbound,
                     &outbound,
+                    &manager.activities,
+                    &mut heartbeat,
                 ) {
-                    Err(DiscordError::IoError(ref err)) if err.kind() == ErrorKind::WouldBlock => {}
-                    Err(DiscordError::IoError(_)) | Err(DiscordError::ConnectionClosed) => {
-                        manager.disconnect()
+                    Err(ref err) if err.io_would_block() => {}
+                    Err(DiscordError::IoError(_))
+                    | Err(DiscordError::ConnectionClosed)
+                    | Err(DiscordError::HeartbeatTimeout) => manager.disconnect(),
+                    Err(DiscordError::Closed { code, message }) => {
+                        report_close(
+                            &mut manager.event_handler_registry,
+                            &manager.pending,
+                            &manager.last_close,
+                            code,
+                            &message,
+                        );
+                        manager.disconnect();
                     }
                     Err(DiscordError::RecvTimeoutError(_)) => continue,
                     Err(why) => trace!("discord error: {}", why),
                     _ => {}
                 }
-
-                thread::sleep(time::Duration::from_millis(500));
             }
             None => match manager.connect() {
                 Err(err) => {
-                    if !err.io_would_block() {
-                        error!("Failed to connect: {:?}", err)
+                    if let DiscordError::Closed { code, message } = &err {
+                        report_close(
+                            &mut manager.event_handler_registry,
+                            &manager.pending,
+                            &manager.last_close,
+                            *code,
+                            message,
+                        );
                     }
 
-                    crate::STARTED.store(false, Ordering::Relaxed);
+                    match manager.config.reconnect.delay(attempt) {
+                        Some(delay) => {
+                            attempt += 1;
+                            trace!(
+                                "Failed to connect: {}, retry {} in {:?}",
+                                err,
+                                attempt,
+                                delay
+                            );
+                            if manager.stop.1.recv_timeout(delay).is_ok() {
+                                break;
+                            }
+                        }
+                        None => {
+                            error!("Failed to connect: {:?}", err);
 
-                    break;
+                            break;
+                        }
+                    }
+                }
+                _ => {
+                    attempt = 0;
+                    heartbeat = manager.heartbeat();
+                    manager.handshake_completed = true;
                 }
-                _ => manager.handshake_completed = true,
             },
         }
     }
+
+    if let Some(ref connection) = *manager.connection.clone() {
+        trace!("Closing connection");
+        if let Err(why) = close_message().and_then(|msg| connection.lock().send(&msg)) {
+            trace!("Failed to send close frame: {}", why);
+        }
+        manager.disconnect();
+    }
+    manager.started.store(false, Ordering::Release);
 }
 
 fn send_and_receive(
-    connection: &mut SocketConnection,
-    _event_handler_registry: &mut HandlerRegistry<'_>,
+    connection: &mut Framed,
+    event_handler_registry: &mut HandlerRegistry,
+    pending: &PendingRequests,
     _inbound: &mut Tx,
     outbound: &Rx,
+    activities: &Arc<Activities>,
+    heartbeat: &mut Heartbeat,
 ) -> Result<()> {
-    while let Ok(msg) = outbound.try_recv() {
+    let mut next = match outbound.recv_timeout(POLL_INTERVAL) {
+        Ok(msg) => Some(msg),
+        Err(RecvTimeoutError::Timeout) => None,
+        Err(why) => return Err(why.into()),
+    };
+
+    while let Some(msg) = next {
         trace!("Sending message");
         connection.send(&msg)?;
         trace!("Sent message");
+        next = outbound.try_recv().ok();
+    }
+
+    if let Some(message) = due_activity(activities, pending) {
+        connection.send(&message)?;
+    }
+
+    loop {
+        let msg = match connection.recv() {
+            Ok(msg) => msg,
+            Err(why) if why.io_would_block() => break,
+            Err(why) => return Err(why),
+        };
+
+        heartbeat.received(Instant::now());
+        match msg.opcode {
+            OpCode::Ping => connection.send(&pong_message(&msg))?,
+            OpCode::Pong => {}
+            OpCode::Close => return Err(close_error(&msg)),
+            _ => handle_inbound(event_handler_registry, pending, msg)?,
+        }
+    }
+
+    match heartbeat.poll(Instant::now()) {
+        Beat::Ping => connection.send(&ping_message()?),
+        Beat::Expired => Err(DiscordError::HeartbeatTimeout),
+        Beat::Idle => Ok(()),
+    }
+}
+
+/// Route a frame received from Discord to the command waiting for it or the handlers
+/// registered for it.
+///
+/// Anything that is neither a response nor a `DISPATCH` of a known [`Event`] goes to the
+/// catch-all handlers.
+pub fn handle_inbound(
+    event_handler_registry: &mut HandlerRegistry,
+    pending: &PendingRequests,
+    msg: Message,
+) -> Result<()> {
+    let payload: JsonValue = serde_json::from_str(&msg.payload)?;
+
+    if msg.opcode == OpCode::Frame {
+        if payload["cmd"] == "DISPATCH" {
+            if let Ok(event) = Event::deserialize(&payload["evt"]) {
+                return event_handler_registry.handle(event, payload["data"].clone());
+            }
+        } else if let Some(payload) = pending.resolve(payload) {
+            return event_handler_registry.handle_unhandled(payload);
+        } else {
+            return Ok(());
+        }
+    }
+
+    event_handler_registry.handle_unhandled(payload)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{reader::FrameReader, Connection};
+    use super::*;
+    use crate::event_handler::InlineExecutor;
+    use crossbeam_channel::{unbounded, TryRecvError};
+    use serde_json::json;
+    use std::io::{self, Read, Write};
+
+    fn frame(payload: JsonValue) -> Message {
+        Message::new(OpCode::Frame, payload).unwrap()
+    }
+
+    /// One end of an in-memory duplex pipe
+    struct Pipe {
+        rx: Receiver<Vec<u8>>,
+        tx: Sender<Vec<u8>>,
+        buf: Vec<u8>,
+    }
+
+    fn pipe() -> (Pipe, Pipe) {
+        let (client_tx, server_rx) = unbounded();
+        let (server_tx, client_rx) = unbounded();
+
+        (
+            Pipe {
+                rx: client_rx,
+                tx: client_tx,
+                buf: Vec::new(),
+            },
+            Pipe {
+                rx: server_rx,
+                tx: server_tx,
+                buf: Vec::new(),
+            },
+        )
     }
 
-    Ok(())
+    impl Read for Pipe {
+        fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+            if self.buf.is_empty() {
+                match self.rx.try_recv() {
+                    Ok(bytes) => self.buf = bytes,
+                    Err(TryRecvError::Empty) => return Err(io::ErrorKind::WouldBlock.into()),
+                    Err(TryRecvError::Disconnected) => return Ok(0),
+                }
+            }
+
+            let len = out.len().min(self.buf.len());
+            out[..len].copy_from_slice(&self.buf[..len]);
+            self.buf.drain(..len);
+            Ok(len)
+        }
+    }
+
+    impl Write for Pipe {
+        fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+            self.tx
+                .send(bytes.to_vec())
+                .map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))?;
+            Ok(bytes.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl std::fmt::Debug for Pipe {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            f.write_str("Pipe")
+        }
+    }
+
+    /// Hands out the client ends of pipes, one per connection attempt
+    #[derive(Debug)]
+    struct PipeConnector(Mutex<Receiver<Pipe>>);
+
+    impl Connector for PipeConnector {
+        fn endpoints(&self, _: Option<u8>, _: &[PathBuf]) -> Vec<Endpoint> {
+            vec![Endpoint {
+                index: 0,
+                path: PathBuf::from("memory"),
+            }]
+        }
+
+        fn connect(&self, _: &Endpoint) -> Result<Box<dyn Connection>> {
+            match self.0.lock().try_recv() {
+                Ok(pipe) => Ok(Box::new(pipe)),
+                Err(_) => Err(io::Error::from(io::ErrorKind::NotFound).into()),
+            }
+        }
+    }
+
+    /// The Discord side of a pipe
+    struct Server {
+        pipe: Pipe,
+        reader: FrameReader,
+    }
+
+    impl Server {
+        fn recv(&mut self) -> Message {
+            for _ in 0..200 {
+                match self.reader.read_frame(&mut self.pipe) {
+                    Ok(msg) => return msg,
+                    Err(why) if why.io_would_block() => thread::sleep(Duration::from_millis(10)),
+                    Err(why) => panic!("server failed to read: {}", why),
+                }
+            }
+            panic!("no frame received")
+        }
+
+        fn send(&mut self, payload: JsonValue) {
+            self.pipe
+                .write_all(&frame(payload).encode().unwrap())
+                .unwrap();
+        }
+
+        fn accept(&mut self) {
+            assert_eq!(self.recv().opcode, OpCode::Handshake);
+            self.send(json!({ "cmd": "DISPATCH", "evt": "READY", "data": { "v": 1 } }));
+        }
+    }
+
+    /// A manager connecting over in-memory pipes, and the server ends of those pipes
+    fn memory_manager(connections: usize, registry: HandlerRegistry) -> (Manager, Vec<Server>) {
+        let (tx, rx) = unbounded();
+        let servers = (0..connections)
+            .map(|_| {
+                let (client, server) = pipe();
+                tx.send(client).unwrap();
+                Server {
+                    pipe: server,
+                    reader: FrameReader::default(),
+                }
+            })
+            .collect();
+
+        let config = Config {
+            connector: Arc::new(PipeConnector(Mutex::new(rx))),
+            ..Config::default()
+        };
+
+        (Manager::new(1, config, registry), servers)
+    }
+
+    #[test]
+    fn dispatches_known_events() {
+        let (tx, rx) = unbounded();
+        let mut registry = HandlerRegistry::new(Arc::new(InlineExecutor));
+        registry.register(Event::ActivityJoin, move |ctx| tx.send(ctx.event).unwrap());
+        registry.register_unhandled(|_| panic!("event should have been handled"));
+
+        handle_inbound(
+            &mut registry,
+            &PendingRequests::default(),
+            frame(json!({
+                "cmd": "DISPATCH",
+                "evt": "ACTIVITY_JOIN",
+                "data": { "secret": "s3cret" }
+            })),
+        )
+        .unwrap();
+
+        assert_eq!(rx.try_recv().unwrap(), json!({ "secret": "s3cret" }));
+    }
+
+    #[test]
+    fn forwards_unknown_frames_to_catch_all() {
+        let (tx, rx) = unbounded();
+        let mut registry = HandlerRegistry::new(Arc::new(InlineExecutor));
+        registry.register_unhandled(move |ctx| tx.send(ctx.event).unwrap());
+
+        let unknown_event = json!({ "cmd": "DISPATCH", "evt": "VOICE_STATE_CREATE", "data": {} });
+        let response = json!({ "cmd": "SET_ACTIVITY", "data": null, "nonce": "1" });
+
+        let pending = PendingRequests::default();
+
+        handle_inbound(&mut registry, &pending, frame(unknown_event.clone())).unwrap();
+        handle_inbound(&mut registry, &pending, frame(response.clone())).unwrap();
+        handle_inbound(
+            &mut registry,
+            &pending,
+            Message::new(OpCode::Pong, json!({})).unwrap(),
+        )
+        .unwrap();
+
+        assert_eq!(rx.try_recv().unwrap(), unknown_event);
+        assert_eq!(rx.try_recv().unwrap(), response);
+        assert_eq!(rx.try_recv().unwrap(), json!({}));
+    }
+
+    #[test]
+    fn routes_responses_to_waiting_commands() {
+        let mut registry = HandlerRegistry::new(Arc::new(InlineExecutor));
+        registry.register_unhandled(|_| panic!("response should have been consumed"));
+
+        let pending = PendingRequests::default();
+        let response = pending.register("nonce".to_owned());
+
+        let payload = json!({ "cmd": "SET_ACTIVITY", "data": null, "nonce": "nonce" });
+        handle_inbound(&mut registry, &pending, frame(payload.clone())).unwrap();
+
+        assert_eq!(response.try_recv().unwrap().unwrap(), payload);
+    }
+
+    #[test]
+    fn rejects_closed_handshake() {
+        let close = Message::new(
+            OpCode::Close,
+            json!({ "code": 4000, "message": "Invalid Client ID" }),
+        )
+        .unwrap();
+
+        match ready_data(&close) {
+            Err(DiscordError::Closed { code, message }) => {
+                assert_eq!(code, CloseCode::InvalidClientId);
+                assert_eq!(message, "Invalid Client ID");
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn reports_close_to_handlers_and_commands() {
+        let (tx, rx) = unbounded();
+        let mut registry = HandlerRegistry::new(Arc::new(InlineExecutor));
+        registry.register(Event::Error, move |ctx| tx.send(ctx.event).unwrap());
+
+        let pending = PendingRequests::default();
+        let response = pending.register("nonce".to_owned());
+        let last_close = LastClose::default();
+
+        report_close(
+            &mut registry,
+            &pending,
+            &last_close,
+            CloseCode::RateLimited,
+            "Slow down",
+        );
+
+        assert_eq!(
+            rx.try_recv().unwrap(),
+            json!({ "code": 4002, "message": "Slow down" })
+        );
+        assert!(matches!(
+            response.try_recv().unwrap(),
+            Err(DiscordError::Closed {
+                code: CloseCode::RateLimited,
+                ..
+            })
+        ));
+        assert!(matches!(
+            take_close_error(&last_close),
+            Some(DiscordError::Closed {
+                code: CloseCode::RateLimited,
+                ..
+            })
+        ));
+        assert!(take_close_error(&last_close).is_none());
+    }
+
+    #[test]
+    fn state_is_per_manager() {
+        let first = Manager::new(
+            1,
+            Config::default(),
+            HandlerRegistry::new(Arc::new(InlineExecutor)),
+        );
+        let second = Manager::new(
+            2,
+            Config::default(),
+            HandlerRegistry::new(Arc::new(InlineExecutor)),
+        );
+
+        first.ready.store(true, Ordering::Release);
+
+        assert!(first.clone().is_ready());
+        assert!(!second.is_ready());
+        assert!(!first.is_started());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn shutdown_sends_close_and_joins() {
+        use super::super::reader::FrameReader;
+        use std::{io::Write, os::unix::net::UnixListener};
+
+        let dir = std::env::temp_dir().join(crate::utils::nonce());
+        std::fs::create_dir_all(&dir).unwrap();
+        let listener = UnixListener::bind(dir.join("discord-ipc-0")).unwrap();
+
+        let config = Config {
+            ipc_dirs: vec![dir.clone()],
+            ..Config::default()
+        };
+        let mut manager = Manager::new(1, config, HandlerRegistry::new(Arc::new(InlineExecutor)));
+        manager.start();
+        assert!(manager.is_started());
+
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut reader = FrameReader::default();
+        let handshake = reader.read_frame(&mut stream).unwrap();
+        assert_eq!(handshake.opcode, OpCode::Handshake);
+
+        let ready = frame(json!({ "cmd": "DISPATCH", "evt": "READY", "data": { "v": 1 } }));
+        stream.write_all(&ready.encode().unwrap()).unwrap();
+        for _ in 0..100 {
+            if manager.is_ready() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(20));
+        }
+        assert!(manager.is_ready());
+
+        manager.shutdown();
+
+        assert_eq!(
+            reader.read_frame(&mut stream).unwrap().opcode,
+            OpCode::Close
+        );
+        assert!(!manager.is_ready());
+        assert!(!manager.is_started());
+        std::fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn talks_to_discord_over_any_connection() {
+        let (tx, rx) = unbounded();
+        let mut registry = HandlerRegistry::new(Arc::new(InlineExecutor));
+        registry.register(Event::ActivityJoin, move |ctx| tx.send(ctx.event).unwrap());
+
+        let (mut manager, mut servers) = memory_manager(1, registry);
+        let mut server = servers.remove(0);
+        manager.start();
+        server.accept();
+
+        server
+            .send(json!({ "cmd": "DISPATCH", "evt": "ACTIVITY_JOIN", "data": { "secret": "s" } }));
+        assert_eq!(
+            rx.recv_timeout(Duration::from_secs(2)).unwrap(),
+            json!({ "secret": "s" })
+        );
+
+        let command = {
+            let manager = manager.clone();
+            thread::spawn(move || {
+                let message = frame(json!({ "cmd": "SET_ACTIVITY", "nonce": "n" }));
+                manager.execute("n".to_owned(), message, None)
+            })
+        };
+        let sent: JsonValue = serde_json::from_str(&server.recv().payload).unwrap();
+        assert_eq!(sent["cmd"], "SET_ACTIVITY");
+        server.send(json!({ "cmd": "SET_ACTIVITY", "data": null, "nonce": "n" }));
+        assert_eq!(command.join().unwrap().unwrap()["nonce"], "n");
+
+        manager.shutdown();
+        assert_eq!(server.recv().opcode, OpCode::Close);
+    }
+
+    #[test]
+    fn starting_twice_keeps_one_io_thread() {
+        let (mut manager, servers) =
+            memory_manager(2, HandlerRegistry::new(Arc::new(InlineExecutor)));
+        let mut servers = servers.into_iter();
+        manager.start();
+        manager.start();
+
+        let mut first = servers.next().unwrap();
+        first.accept();
+        for _ in 0..100 {
+            if manager.is_ready() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(20));
+        }
+        assert!(manager.is_ready());
+
+        // A second IO thread would have taken the spare connection by now
+        thread::sleep(Duration::from_millis(200));
+        assert!(manager
+            .config
+            .connector
+            .connect(&Endpoint {
+                index: 0,
+                path: PathBuf::from("memory"),
+            })
+            .is_ok());
+
+        manager.shutdown();
+        assert_eq!(first.recv().opcode, OpCode::Close);
+    }
+
+    #[test]
+    fn reconnects_when_the_connection_drops() {
+        let (tx, rx) = unbounded();
+        let mut registry = HandlerRegistry::new(Arc::new(InlineExecutor));
+        registry.register(Event::Ready, move |_| tx.send(()).unwrap());
+
+        let (mut manager, servers) = memory_manager(2, registry);
+        let mut servers = servers.into_iter();
+        manager.start();
+
+        let mut first = servers.next().unwrap();
+        first.accept();
+        rx.recv_timeout(Duration::from_secs(2)).unwrap();
+        drop(first);
+
+        let mut second = servers.next().unwrap();
+        second.accept();
+        rx.recv_timeout(Duration::from_secs(2)).unwrap();
+        assert!(manager.is_ready());
+
+        manager.shutdown();
+    }
 }
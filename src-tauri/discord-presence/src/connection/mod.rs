@@ -1,15 +1,71 @@
 mod base;
+mod connector;
+mod heartbeat;
+mod info;
 mod manager;
+mod paths;
+mod pending;
+mod rate_limit;
+mod reader;
+mod reconnect;
+mod record;
 
+#[cfg(feature = "tokio")]
+mod async_manager;
+
+#[cfg(all(unix, any(test, feature = "testing")))]
+pub(crate) use base::close_payload;
 pub use base::Connection;
-pub use manager::Manager;
+pub use connector::{Connector, Endpoint, IpcConnector, TcpConnector};
+pub use heartbeat::{DEFAULT_HEARTBEAT_INTERVAL, DEFAULT_HEARTBEAT_TIMEOUT};
+pub use info::{ConnectionInfo, DiscordBuild};
+pub use manager::{Config, Manager, DEFAULT_COMMAND_TIMEOUT};
+pub use paths::IPC_PATH_ENV;
+pub use rate_limit::RateLimit;
+#[cfg(all(unix, any(test, feature = "testing")))]
+pub(crate) use reader::FrameReader;
+pub use reader::DEFAULT_MAX_FRAME_SIZE;
+pub use reconnect::ReconnectPolicy;
+pub use record::{Direction, Recorder, ReplayConnector};
+
+#[cfg(feature = "tokio")]
+pub use async_manager::AsyncManager;
 
 cfg_if::cfg_if! {
     if #[cfg(unix)] {
         mod unix;
-        pub use unix::UnixConnection as SocketConnection;
+        use unix as platform;
     } else if #[cfg(windows)] {
         mod windows;
-        pub use windows::WindowsConnection as SocketConnection;
+        use windows as platform;
+    }
+}
+
+/// Discord binds the first free socket out of `discord-ipc-0` to `discord-ipc-9`
+pub const SOCKET_COUNT: u8 = 10;
+
+/// The order in which socket indices are tried, starting with the preferred one
+pub fn socket_order(preferred: Option<u8>) -> Vec<u8> {
+    let preferred = preferred.filter(|index| *index < SOCKET_COUNT);
+
+    preferred
+        .into_iter()
+        .chain((0..SOCKET_COUNT).filter(|index| Some(*index) != preferred))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tries_every_socket_in_order() {
+        assert_eq!(socket_order(None), (0..10).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn tries_preferred_socket_first() {
+        assert_eq!(socket_order(Some(3)), vec![3, 0, 1, 2, 4, 5, 6, 7, 8, 9]);
+        assert_eq!(socket_order(Some(42)), (0..10).collect::<Vec<_>>());
     }
 }
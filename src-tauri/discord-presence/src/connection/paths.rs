@@ -0,0 +1,138 @@
+use std::{
+    env,
+    path::{Path, PathBuf},
+};
+
+/// Environment variable that replaces the built-in list of IPC directories
+///
+/// Accepts one or more directories separated like `PATH`.
+pub const IPC_PATH_ENV: &str = "DISCORD_IPC_PATH";
+
+/// Directories below the runtime directory used by sandboxed or alternative clients
+#[cfg(unix)]
+pub const SANDBOX_SUBDIRS: &[&str] = &[
+    "app/com.discordapp.Discord",
+    "app/com.discordapp.DiscordPTB",
+    "app/com.discordapp.DiscordCanary",
+    "snap.discord",
+    "snap.discord-canary",
+    ".flatpak/dev.vencord.Vesktop/xdg-run",
+    "app/dev.vencord.Vesktop",
+];
+
+/// The directories to look for `discord-ipc-N` in, highest priority first
+///
+/// `extra` directories come before the platform defaults. Setting [`IPC_PATH_ENV`]
+/// replaces the platform defaults entirely.
+pub fn candidate_dirs(extra: &[PathBuf], defaults: Vec<PathBuf>) -> Vec<PathBuf> {
+    candidate_dirs_with(extra, defaults, |key| env::var_os(key))
+}
+
+fn candidate_dirs_with<F>(extra: &[PathBuf], defaults: Vec<PathBuf>, var: F) -> Vec<PathBuf>
+where
+    F: Fn(&str) -> Option<std::ffi::OsString>,
+{
+    let defaults = match var(IPC_PATH_ENV).filter(|value| !value.is_empty()) {
+        Some(value) => env::split_paths(&value).collect(),
+        None => defaults,
+    };
+
+    let mut dirs: Vec<PathBuf> = Vec::with_capacity(extra.len() + defaults.len());
+    for dir in extra.iter().cloned().chain(defaults) {
+        if !dirs.contains(&dir) {
+            dirs.push(dir);
+        }
+    }
+
+    dirs
+}
+
+/// The path of socket `index` inside `dir`
+pub fn socket_path(dir: &Path, index: u8) -> PathBuf {
+    dir.join(format!("discord-ipc-{}", index))
+}
+
+/// The runtime directories Discord may bind its sockets in, plus their sandbox subdirectories
+#[cfg(unix)]
+pub fn unix_dirs<F>(var: F) -> Vec<PathBuf>
+where
+    F: Fn(&str) -> Option<String>,
+{
+    let mut roots: Vec<PathBuf> = ["XDG_RUNTIME_DIR", "TMPDIR", "TMP", "TEMP"]
+        .iter()
+        .filter_map(|key| var(key))
+        .map(PathBuf::from)
+        .collect();
+    roots.push(PathBuf::from("/tmp"));
+    roots.dedup();
+
+    let mut dirs = roots.clone();
+    for root in &roots {
+        dirs.extend(SANDBOX_SUBDIRS.iter().map(|subdir| root.join(subdir)));
+    }
+
+    dirs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::OsString;
+
+    #[test]
+    fn extra_dirs_come_first() {
+        let dirs = candidate_dirs_with(
+            &[PathBuf::from("/custom"), PathBuf::from("/run/user/1000")],
+            vec![PathBuf::from("/run/user/1000"), PathBuf::from("/tmp")],
+            |_| None,
+        );
+
+        assert_eq!(
+            dirs,
+            vec![
+                PathBuf::from("/custom"),
+                PathBuf::from("/run/user/1000"),
+                PathBuf::from("/tmp"),
+            ]
+        );
+    }
+
+    #[test]
+    fn env_var_replaces_defaults() {
+        let value = env::join_paths(["/a", "/b"]).unwrap();
+        let dirs = candidate_dirs_with(
+            &[PathBuf::from("/custom")],
+            vec![PathBuf::from("/tmp")],
+            |key| (key == IPC_PATH_ENV).then(|| value.clone()),
+        );
+
+        assert_eq!(
+            dirs,
+            vec![
+                PathBuf::from("/custom"),
+                PathBuf::from("/a"),
+                PathBuf::from("/b"),
+            ]
+        );
+    }
+
+    #[test]
+    fn empty_env_var_is_ignored() {
+        let dirs = candidate_dirs_with(&[], vec![PathBuf::from("/tmp")], |_| Some(OsString::new()));
+
+        assert_eq!(dirs, vec![PathBuf::from("/tmp")]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn unix_dirs_include_sandboxes() {
+        let dirs = unix_dirs(|key| (key == "XDG_RUNTIME_DIR").then(|| "/run/user/1000".to_owned()));
+
+        assert_eq!(dirs[0], PathBuf::from("/run/user/1000"));
+        assert_eq!(dirs[1], PathBuf::from("/tmp"));
+        assert!(dirs.contains(&PathBuf::from("/run/user/1000/snap.discord")));
+        assert!(dirs.contains(&PathBuf::from(
+            "/run/user/1000/app/com.discordapp.DiscordCanary"
+        )));
+    }
+}
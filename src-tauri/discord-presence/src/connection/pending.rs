@@ -0,0 +1,136 @@
+use crate::error::{DiscordError, ErrorCode, Result};
+use crossbeam_channel::{bounded, Receiver};
+use parking_lot::Mutex;
+use serde_json::Value as JsonValue;
+use std::{collections::HashMap, sync::Arc};
+
+type Responder = Box<dyn FnOnce(Result<JsonValue>) + Send>;
+
+/// Commands waiting for Discord's answer, keyed by nonce
+#[derive(Clone, Default)]
+pub struct PendingRequests {
+    requests: Arc<Mutex<HashMap<String, Responder>>>,
+}
+
+impl PendingRequests {
+    /// Start waiting for the response carrying `nonce`
+    pub fn register(&self, nonce: String) -> Receiver<Result<JsonValue>> {
+        let (tx, rx) = bounded(1);
+        self.register_with(nonce, move |response| {
+            // The caller may have timed out in the meantime
+            tx.send(response).ok();
+        });
+        rx
+    }
+
+    /// Call `responder` with the response carrying `nonce` once it arrives
+    pub fn register_with<F>(&self, nonce: String, responder: F)
+    where
+        F: FnOnce(Result<JsonValue>) + Send + 'static,
+    {
+        self.requests.lock().insert(nonce, Box::new(responder));
+    }
+
+    /// Stop waiting for `nonce`, e.g. because the caller gave up
+    pub fn cancel(&self, nonce: &str) {
+        self.requests.lock().remove(nonce);
+    }
+
+    /// Hand a response to whoever is waiting for its nonce
+    ///
+    /// Returns the payload back if nobody is waiting for it.
+    pub fn resolve(&self, payload: JsonValue) -> Option<JsonValue> {
+        let waiting = payload["nonce"]
+            .as_str()
+            .and_then(|nonce| self.requests.lock().remove(nonce));
+
+        match waiting {
+            Some(respond) => {
+                respond(response_result(payload));
+                None
+            }
+            None => Some(payload),
+        }
+    }
+
+    /// Fail every outstanding request, e.g. after the connection dropped
+    pub fn fail_all<F>(&self, error: F)
+    where
+        F: Fn() -> DiscordError,
+    {
+        let requests: Vec<_> = self.requests.lock().drain().collect();
+        for (_, respond) in requests {
+            respond(Err(error()));
+        }
+    }
+}
+
+/// Turn a response payload into an error if Discord rejected the command
+fn response_result(payload: JsonValue) -> Result<JsonValue> {
+    if payload["evt"] != "ERROR" {
+        return Ok(payload);
+    }
+
+    let data = &payload["data"];
+    Err(DiscordError::Rpc {
+        code: data["code"]
+            .as_u64()
+            .map_or(ErrorCode::Unknown, |code| ErrorCode::from(code as u32)),
+        message: data["message"].as_str().unwrap_or_default().to_owned(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn resolves_matching_nonce() {
+        let pending = PendingRequests::default();
+        let rx = pending.register("abc".to_owned());
+
+        let unrelated = json!({ "cmd": "SET_ACTIVITY", "nonce": "xyz" });
+        assert_eq!(pending.resolve(unrelated.clone()), Some(unrelated));
+        assert!(rx.try_recv().is_err());
+
+        let response = json!({ "cmd": "SET_ACTIVITY", "data": null, "nonce": "abc" });
+        assert_eq!(pending.resolve(response.clone()), None);
+        assert_eq!(rx.try_recv().unwrap().unwrap(), response);
+    }
+
+    #[test]
+    fn turns_error_responses_into_rpc_errors() {
+        let pending = PendingRequests::default();
+        let rx = pending.register("abc".to_owned());
+
+        pending.resolve(json!({
+            "cmd": "SET_ACTIVITY",
+            "evt": "ERROR",
+            "data": { "code": 4000, "message": "child \"activity\" fails" },
+            "nonce": "abc"
+        }));
+
+        match rx.try_recv().unwrap() {
+            Err(DiscordError::Rpc { code, message }) => {
+                assert_eq!(code, ErrorCode::InvalidPayload);
+                assert_eq!(message, "child \"activity\" fails");
+            }
+            other => panic!("unexpected response {:?}", other),
+        }
+    }
+
+    #[test]
+    fn fails_outstanding_requests() {
+        let pending = PendingRequests::default();
+        let rx = pending.register("abc".to_owned());
+
+        pending.fail_all(|| DiscordError::ConnectionClosed);
+
+        assert!(matches!(
+            rx.try_recv().unwrap(),
+            Err(DiscordError::ConnectionClosed)
+        ));
+        assert!(pending.resolve(json!({ "nonce": "abc" })).is_some());
+    }
+}
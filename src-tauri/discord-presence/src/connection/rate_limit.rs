@@ -0,0 +1,210 @@
+use std::{
+    collections::VecDeque,
+    fmt::Debug,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+/// How many activity updates may be sent within a sliding window
+///
+/// Discord drops updates beyond roughly five every 20 seconds, which is the default.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimit {
+    /// Updates allowed within `per`
+    pub updates: usize,
+    /// Length of the sliding window
+    pub per: Duration,
+}
+
+impl Default for RateLimit {
+    fn default() -> Self {
+        Self {
+            updates: 5,
+            per: Duration::from_secs(20),
+        }
+    }
+}
+
+/// Tells the time, so tests can move it forward by hand
+pub trait Clock: Debug + Send + Sync {
+    /// The current time
+    fn now(&self) -> Instant;
+}
+
+/// The real clock
+#[derive(Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// Holds back updates beyond a [`RateLimit`], keeping only the latest of them
+#[derive(Debug)]
+pub struct RateLimiter<T> {
+    limit: RateLimit,
+    clock: Arc<dyn Clock>,
+    sent: VecDeque<Instant>,
+    pending: Option<T>,
+}
+
+impl<T> RateLimiter<T> {
+    pub fn new(limit: RateLimit, clock: Arc<dyn Clock>) -> Self {
+        Self {
+            limit,
+            clock,
+            sent: VecDeque::new(),
+            pending: None,
+        }
+    }
+
+    /// Queue `update`, replacing any update still waiting
+    pub fn queue(&mut self, update: T) {
+        self.pending = Some(update);
+    }
+
+    /// Take the waiting update if the limit allows sending it now, counting it as sent
+    pub fn poll(&mut self) -> Option<T> {
+        self.pending.as_ref()?;
+
+        let now = self.clock.now();
+        self.forget_before(now);
+        if self.sent.len() >= self.limit.updates {
+            return None;
+        }
+
+        self.sent.push_back(now);
+        self.pending.take()
+    }
+
+    /// Count an update sent without going through the queue, dropping the waiting one
+    ///
+    /// The waiting update is older than the one just sent, so it must not overwrite it.
+    pub fn bypass(&mut self) {
+        let now = self.clock.now();
+        self.forget_before(now);
+        self.sent.push_back(now);
+        self.discard();
+    }
+
+    /// Drop the waiting update without sending it
+    pub fn discard(&mut self) {
+        self.pending = None;
+    }
+
+    /// Forget sends that left the window ending at `now`.
+    fn forget_before(&mut self, now: Instant) {
+        while let Some(&oldest) = self.sent.front() {
+            if now.saturating_duration_since(oldest) < self.limit.per {
+                break;
+            }
+            self.sent.pop_front();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parking_lot::Mutex;
+
+    /// A clock that only moves when told to
+    #[derive(Debug, Clone)]
+    struct ManualClock(Arc<Mutex<Instant>>);
+
+    impl ManualClock {
+        fn new() -> Self {
+            Self(Arc::new(Mutex::new(Instant::now())))
+        }
+
+        fn advance(&self, by: Duration) {
+            *self.0.lock() += by;
+        }
+    }
+
+    impl Clock for ManualClock {
+        fn now(&self) -> Instant {
+            *self.0.lock()
+        }
+    }
+
+    fn limiter(clock: &ManualClock) -> RateLimiter<&'static str> {
+        RateLimiter::new(RateLimit::default(), Arc::new(clock.clone()))
+    }
+
+    #[test]
+    fn sends_immediately_within_the_limit() {
+        let clock = ManualClock::new();
+        let mut limiter = limiter(&clock);
+
+        assert_eq!(limiter.poll(), None);
+        for update in &["1", "2", "3", "4", "5"] {
+            limiter.queue(update);
+            assert_eq!(limiter.poll(), Some(*update));
+            clock.advance(Duration::from_secs(1));
+        }
+    }
+
+    #[test]
+    fn keeps_only_the_latest_update_beyond_the_limit() {
+        let clock = ManualClock::new();
+        let mut limiter = limiter(&clock);
+
+        for update in &["1", "2", "3", "4", "5"] {
+            limiter.queue(update);
+            limiter.poll();
+        }
+        limiter.queue("6");
+        limiter.queue("7");
+        assert_eq!(limiter.poll(), None);
+
+        clock.advance(Duration::from_secs(19));
+        assert_eq!(limiter.poll(), None);
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(limiter.poll(), Some("7"));
+        assert_eq!(limiter.poll(), None);
+    }
+
+    #[test]
+    fn window_slides_with_each_send() {
+        let clock = ManualClock::new();
+        let mut limiter = limiter(&clock);
+
+        for update in &["1", "2", "3", "4", "5"] {
+            limiter.queue(update);
+            limiter.poll();
+            clock.advance(Duration::from_secs(4));
+        }
+
+        // The first update left the window, the second leaves it four seconds later
+        limiter.queue("6");
+        assert_eq!(limiter.poll(), Some("6"));
+        limiter.queue("7");
+        assert_eq!(limiter.poll(), None);
+        clock.advance(Duration::from_secs(4));
+        assert_eq!(limiter.poll(), Some("7"));
+    }
+
+    #[test]
+    fn direct_updates_count_and_drop_the_queued_one() {
+        let clock = ManualClock::new();
+        let mut limiter = RateLimiter::new(
+            RateLimit {
+                updates: 1,
+                per: Duration::from_secs(20),
+            },
+            Arc::new(clock.clone()),
+        );
+
+        limiter.queue("stale");
+        limiter.bypass();
+        clock.advance(Duration::from_secs(20));
+        assert_eq!(limiter.poll(), None);
+
+        limiter.bypass();
+        limiter.queue("queued");
+        assert_eq!(limiter.poll(), None);
+    }
+}
@@ -0,0 +1,225 @@
+use crate::{
+    error::{DiscordError, Result},
+    models::message::Message,
+};
+use byteorder::{ByteOrder, LittleEndian};
+use bytes::{Buf, BytesMut};
+use std::io::Read;
+
+/// Size of the opcode and length prefix in front of every frame
+pub const HEADER_SIZE: usize = 8;
+
+/// Largest frame accepted unless configured otherwise
+pub const DEFAULT_MAX_FRAME_SIZE: usize = 1024 * 1024;
+
+const READ_CHUNK_SIZE: usize = 4096;
+
+/// Buffers raw bytes from the socket and splits them into frames
+///
+/// Leftover bytes after a frame are kept for the next one, so frames may be
+/// split across reads or arrive several at a time.
+#[derive(Debug)]
+pub struct FrameReader {
+    buf: BytesMut,
+    max_frame_size: usize,
+    discard: usize,
+}
+
+impl FrameReader {
+    /// Create a reader that rejects payloads larger than `max_frame_size` bytes
+    pub fn new(max_frame_size: usize) -> Self {
+        Self {
+            buf: BytesMut::with_capacity(READ_CHUNK_SIZE),
+            max_frame_size,
+            discard: 0,
+        }
+    }
+
+    /// Append bytes received from the socket
+    pub fn extend(&mut self, bytes: &[u8]) {
+        let skipped = self.discard.min(bytes.len());
+        self.discard -= skipped;
+        self.buf.extend_from_slice(&bytes[skipped..]);
+    }
+
+    /// Decode the next complete frame, if one has been buffered
+    ///
+    /// An oversized frame is reported once and then skipped as its bytes arrive.
+    pub fn next_frame(&mut self) -> Result<Option<Message>> {
+        if self.buf.len() < HEADER_SIZE {
+            return Ok(None);
+        }
+
+        let len = LittleEndian::read_u32(&self.buf[4..HEADER_SIZE]) as usize;
+        if len > self.max_frame_size {
+            self.buf.advance(HEADER_SIZE);
+            let skipped = len.min(self.buf.len());
+            self.buf.advance(skipped);
+            self.discard = len - skipped;
+
+            return Err(DiscordError::FrameTooLarge {
+                len,
+                max: self.max_frame_size,
+            });
+        }
+
+        if self.buf.len() < HEADER_SIZE + len {
+            return Ok(None);
+        }
+
+        let frame = self.buf.split_to(HEADER_SIZE + len);
+        Message::decode(&frame).map(Some)
+    }
+
+    /// Read from `reader` until a whole frame is available
+    ///
+    /// Errors from the reader, including [`std::io::ErrorKind::WouldBlock`], are
+    /// returned as-is and any partial frame stays buffered for the next call.
+    pub fn read_frame<R: Read + ?Sized>(&mut self, reader: &mut R) -> Result<Message> {
+        let mut chunk = [0; READ_CHUNK_SIZE];
+
+        loop {
+            if let Some(message) = self.next_frame()? {
+                return Ok(message);
+            }
+
+            let n = reader.read(&mut chunk)?;
+            trace!("Received {} bytes", n);
+
+            if n == 0 {
+                return Err(DiscordError::ConnectionClosed);
+            }
+
+            self.extend(&chunk[..n]);
+        }
+    }
+}
+
+impl Default for FrameReader {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_FRAME_SIZE)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::message::OpCode;
+    use serde_json::json;
+    use std::io::{self, Cursor};
+
+    fn frame(opcode: OpCode, payload: serde_json::Value) -> (Message, Vec<u8>) {
+        let message = Message::new(opcode, payload).unwrap();
+        let bytes = message.encode().unwrap();
+        (message, bytes)
+    }
+
+    /// Hands out at most `chunk` bytes per read, then reports `WouldBlock`
+    struct Trickle {
+        data: Cursor<Vec<u8>>,
+        chunk: usize,
+    }
+
+    impl Read for Trickle {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let len = buf.len().min(self.chunk);
+            match self.data.read(&mut buf[..len])? {
+                0 => Err(io::ErrorKind::WouldBlock.into()),
+                n => Ok(n),
+            }
+        }
+    }
+
+    #[test]
+    fn decodes_fragmented_frame() {
+        let (message, bytes) = frame(OpCode::Frame, json!({ "cmd": "DISPATCH" }));
+        let mut reader = FrameReader::default();
+
+        for byte in &bytes[..bytes.len() - 1] {
+            reader.extend(&[*byte]);
+            assert_eq!(reader.next_frame().unwrap(), None);
+        }
+
+        reader.extend(&bytes[bytes.len() - 1..]);
+        assert_eq!(reader.next_frame().unwrap(), Some(message));
+        assert_eq!(reader.next_frame().unwrap(), None);
+    }
+
+    #[test]
+    fn splits_coalesced_frames() {
+        let (first, mut bytes) = frame(OpCode::Frame, json!({ "n": 1 }));
+        let (second, second_bytes) = frame(OpCode::Ping, json!({ "n": 2 }));
+        let (third, third_bytes) = frame(OpCode::Frame, json!({ "n": 3 }));
+        bytes.extend(second_bytes);
+        bytes.extend(&third_bytes[..5]);
+
+        let mut reader = FrameReader::default();
+        reader.extend(&bytes);
+
+        assert_eq!(reader.next_frame().unwrap(), Some(first));
+        assert_eq!(reader.next_frame().unwrap(), Some(second));
+        assert_eq!(reader.next_frame().unwrap(), None);
+
+        reader.extend(&third_bytes[5..]);
+        assert_eq!(reader.next_frame().unwrap(), Some(third));
+    }
+
+    #[test]
+    fn reads_frames_larger_than_a_chunk() {
+        let (message, bytes) = frame(OpCode::Frame, json!({ "blob": "x".repeat(10_000) }));
+        let mut socket = Trickle {
+            data: Cursor::new(bytes),
+            chunk: 1000,
+        };
+
+        let mut reader = FrameReader::default();
+        assert_eq!(reader.read_frame(&mut socket).unwrap(), message);
+        assert!(reader.read_frame(&mut socket).unwrap_err().io_would_block());
+    }
+
+    #[test]
+    fn keeps_partial_frame_across_would_block() {
+        let (message, bytes) = frame(OpCode::Frame, json!({ "cmd": "SET_ACTIVITY" }));
+        let mut reader = FrameReader::default();
+
+        let mut socket = Trickle {
+            data: Cursor::new(bytes[..10].to_vec()),
+            chunk: 3,
+        };
+        assert!(reader.read_frame(&mut socket).unwrap_err().io_would_block());
+
+        let mut socket = Trickle {
+            data: Cursor::new(bytes[10..].to_vec()),
+            chunk: 3,
+        };
+        assert_eq!(reader.read_frame(&mut socket).unwrap(), message);
+    }
+
+    #[test]
+    fn skips_oversized_frames() {
+        let (_, big) = frame(OpCode::Frame, json!({ "blob": "x".repeat(100) }));
+        let (small, small_bytes) = frame(OpCode::Pong, json!({}));
+        let mut reader = FrameReader::new(64);
+
+        reader.extend(&big[..20]);
+        assert!(matches!(
+            reader.next_frame(),
+            Err(DiscordError::FrameTooLarge { max: 64, .. })
+        ));
+
+        reader.extend(&big[20..]);
+        reader.extend(&small_bytes);
+        assert_eq!(reader.next_frame().unwrap(), Some(small));
+    }
+
+    #[test]
+    fn reports_closed_socket() {
+        let mut reader = FrameReader::default();
+        let mut socket = Cursor::new(Vec::new());
+
+        assert!(matches!(
+            reader.read_frame(&mut socket),
+            Err(DiscordError::ConnectionClosed)
+        ));
+    }
+}
@@ -0,0 +1,128 @@
+use crate::utils;
+use std::time::Duration;
+
+/// How the connection manager retries after failing to connect or losing the connection
+///
+/// The n-th retry waits `initial_delay * multiplier^n`, capped at `max_delay` and
+/// spread out by up to `jitter` (a fraction of the delay) in either direction.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReconnectPolicy {
+    /// Delay before the first retry
+    pub initial_delay: Duration,
+    /// Upper bound for the delay between retries
+    pub max_delay: Duration,
+    /// Factor the delay grows by after every failed attempt
+    pub multiplier: f64,
+    /// Random spread applied to every delay, between `0.0` and `1.0`
+    pub jitter: f64,
+    /// Retries before giving up, `None` to retry forever
+    pub max_attempts: Option<u32>,
+}
+
+impl ReconnectPolicy {
+    /// Retry forever with the default backoff
+    pub fn forever() -> Self {
+        Self::default()
+    }
+
+    /// Give up after the first failed attempt
+    pub fn never() -> Self {
+        Self {
+            max_attempts: Some(0),
+            ..Self::default()
+        }
+    }
+
+    /// Retry at most `max_attempts` times with the default backoff
+    pub fn attempts(max_attempts: u32) -> Self {
+        Self {
+            max_attempts: Some(max_attempts),
+            ..Self::default()
+        }
+    }
+
+    /// The delay before retry number `attempt` (starting at zero), or `None` to give up
+    pub fn delay(&self, attempt: u32) -> Option<Duration> {
+        if self.max_attempts.is_some_and(|max| attempt >= max) {
+            return None;
+        }
+
+        let base = self.initial_delay.as_secs_f64() * self.multiplier.max(1.0).powi(attempt as i32);
+        let base = base.min(self.max_delay.as_secs_f64());
+        let jitter = self.jitter.clamp(0.0, 1.0) * (utils::random_fraction() * 2.0 - 1.0);
+
+        // Jitter can push a delay close to `Duration::MAX` past what a `Duration` holds
+        Some(
+            Duration::try_from_secs_f64((base * (1.0 + jitter)).max(0.0)).unwrap_or(self.max_delay),
+        )
+    }
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        Self {
+            initial_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(60),
+            multiplier: 2.0,
+            jitter: 0.2,
+            max_attempts: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn without_jitter(policy: ReconnectPolicy) -> ReconnectPolicy {
+        ReconnectPolicy {
+            jitter: 0.0,
+            ..policy
+        }
+    }
+
+    #[test]
+    fn backs_off_exponentially_up_to_max() {
+        let policy = without_jitter(ReconnectPolicy::forever());
+
+        assert_eq!(policy.delay(0), Some(Duration::from_secs(1)));
+        assert_eq!(policy.delay(1), Some(Duration::from_secs(2)));
+        assert_eq!(policy.delay(4), Some(Duration::from_secs(16)));
+        assert_eq!(policy.delay(6), Some(Duration::from_secs(60)));
+        assert_eq!(policy.delay(1000), Some(Duration::from_secs(60)));
+    }
+
+    #[test]
+    fn gives_up_after_max_attempts() {
+        let policy = without_jitter(ReconnectPolicy::attempts(2));
+
+        assert!(policy.delay(1).is_some());
+        assert_eq!(policy.delay(2), None);
+        assert_eq!(ReconnectPolicy::never().delay(0), None);
+    }
+
+    #[test]
+    fn jitter_stays_in_bounds() {
+        let policy = ReconnectPolicy {
+            jitter: 0.5,
+            ..ReconnectPolicy::forever()
+        };
+
+        for _ in 0..100 {
+            let delay = policy.delay(2).unwrap();
+            assert!(delay >= Duration::from_secs(2) && delay <= Duration::from_secs(6));
+        }
+    }
+
+    #[test]
+    fn uncapped_delays_do_not_overflow() {
+        let policy = ReconnectPolicy {
+            max_delay: Duration::MAX,
+            ..ReconnectPolicy::forever()
+        };
+
+        for _ in 0..100 {
+            assert!(policy.delay(1000).is_some());
+        }
+    }
+}
@@ -0,0 +1,475 @@
+use super::{reader::FrameReader, Connection, Connector, Endpoint};
+use crate::{
+    error::Result,
+    models::message::{Message, OpCode},
+};
+use num_traits::FromPrimitive;
+use parking_lot::Mutex;
+use serde_json::Value as JsonValue;
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt,
+    fs::File,
+    io::{self, BufRead, BufReader, LineWriter, Read, Write},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Stands in for OAuth2 credentials, which have no place in a recording meant to be shared
+const REDACTED: &str = "[redacted]";
+
+/// Which way a recorded frame travelled
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    /// From the client to Discord
+    Sent,
+    /// From Discord to the client
+    Received,
+}
+
+/// One line of a recording
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Record {
+    direction: Direction,
+    /// Milliseconds since the Unix epoch
+    timestamp: u64,
+    opcode: u32,
+    payload: JsonValue,
+}
+
+impl Record {
+    fn new(direction: Direction, message: &Message) -> Self {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |time| time.as_millis() as u64);
+
+        let mut payload = serde_json::from_str(&message.payload)
+            .unwrap_or_else(|_| JsonValue::String(message.payload.clone()));
+        redact(&mut payload);
+
+        Self {
+            direction,
+            timestamp,
+            opcode: message.opcode as u32,
+            payload,
+        }
+    }
+
+    fn opcode(&self) -> Option<OpCode> {
+        OpCode::from_u32(self.opcode)
+    }
+
+    fn to_message(&self) -> Result<Message> {
+        let opcode = self.opcode().ok_or(crate::DiscordError::Conversion)?;
+        Message::new(opcode, &self.payload)
+    }
+}
+
+/// Replace the access token and authorization code of the OAuth2 commands with [`REDACTED`].
+///
+/// Replays match frames by `cmd` and `nonce` only, so they are not affected.
+fn redact(payload: &mut JsonValue) {
+    if !matches!(
+        payload["cmd"].as_str(),
+        Some("AUTHORIZE") | Some("AUTHENTICATE")
+    ) {
+        return;
+    }
+
+    for (section, field) in &[
+        ("args", "access_token"),
+        ("data", "code"),
+        ("data", "access_token"),
+    ] {
+        // Error responses carry a numeric `code`, which is kept
+        if let Some(secret) = payload
+            .get_mut(*section)
+            .and_then(|section| section.get_mut(*field))
+            .filter(|secret| secret.is_string())
+        {
+            *secret = REDACTED.into();
+        }
+    }
+}
+
+/// Writes every frame sent or received by the client to a JSONL file
+///
+/// Each line holds the `direction`, a `timestamp` in milliseconds, the numeric `opcode`
+/// and the JSON `payload` of one frame. Access tokens and authorization codes are replaced
+/// by a placeholder, so recordings can be shared. Feed the file to a [`ReplayConnector`] to
+/// play the session back.
+#[derive(Clone)]
+pub struct Recorder {
+    out: Arc<Mutex<Box<dyn Write + Send>>>,
+}
+
+impl Recorder {
+    /// Record to a new file at `path`, replacing any existing one
+    pub fn create(path: impl AsRef<Path>) -> io::Result<Self> {
+        Ok(Self::new(LineWriter::new(File::create(path)?)))
+    }
+
+    /// Record to any writer
+    pub fn new(out: impl Write + Send + 'static) -> Self {
+        Self {
+            out: Arc::new(Mutex::new(Box::new(out))),
+        }
+    }
+
+    /// Append `message` to the recording
+    pub(crate) fn record(&self, direction: Direction, message: &Message) {
+        let result = serde_json::to_vec(&Record::new(direction, message))
+            .map_err(io::Error::from)
+            .and_then(|mut line| {
+                line.push(b'\n');
+                self.out.lock().write_all(&line)
+            });
+
+        if let Err(why) = result {
+            error!("Failed to record frame: {}", why);
+        }
+    }
+}
+
+impl fmt::Debug for Recorder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Recorder").finish_non_exhaustive()
+    }
+}
+
+/// Plays a recording made by a [`Recorder`] back to the client instead of talking to Discord
+///
+/// Received frames are handed out in their recorded order, each one only after the client
+/// has sent the frames that preceded it. Nonces of recorded responses are rewritten to match
+/// the commands the client sends now, and heartbeat pings the recording does not contain are
+/// answered directly. Timestamps are ignored, so the replay runs as fast as the client goes.
+#[derive(Debug, Clone)]
+pub struct ReplayConnector {
+    replay: Arc<Mutex<Replay>>,
+}
+
+#[derive(Debug, Default)]
+struct Replay {
+    records: VecDeque<Record>,
+    nonces: HashMap<String, String>,
+    pongs: VecDeque<Message>,
+}
+
+impl ReplayConnector {
+    /// Replay the recording at `path`
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::from_reader(BufReader::new(File::open(path)?))
+    }
+
+    /// Replay a recording read line by line from `reader`
+    pub fn from_reader(reader: impl BufRead) -> io::Result<Self> {
+        let mut records = VecDeque::new();
+        for line in reader.lines() {
+            let line = line?;
+            if !line.trim().is_empty() {
+                records.push_back(serde_json::from_str(&line)?);
+            }
+        }
+
+        Ok(Self {
+            replay: Arc::new(Mutex::new(Replay {
+                records,
+                ..Replay::default()
+            })),
+        })
+    }
+}
+
+impl Connector for ReplayConnector {
+    fn endpoints(&self, _preferred_socket: Option<u8>, _ipc_dirs: &[PathBuf]) -> Vec<Endpoint> {
+        vec![Endpoint {
+            index: 0,
+            path: PathBuf::from("replay"),
+        }]
+    }
+
+    fn connect(&self, _endpoint: &Endpoint) -> Result<Box<dyn Connection>> {
+        if self.replay.lock().records.is_empty() {
+            return Err(io::Error::from(io::ErrorKind::NotFound).into());
+        }
+
+        Ok(Box::new(ReplayStream {
+            replay: self.replay.clone(),
+            decoder: FrameReader::default(),
+            pending: Vec::new(),
+            handshake_sent: false,
+        }))
+    }
+}
+
+struct ReplayStream {
+    replay: Arc<Mutex<Replay>>,
+    decoder: FrameReader,
+    pending: Vec<u8>,
+    handshake_sent: bool,
+}
+
+impl ReplayStream {
+    /// Match a frame from the client against the next recorded frame like it.
+    fn sent(&mut self, message: Message) {
+        let payload: JsonValue = serde_json::from_str(&message.payload).unwrap_or_default();
+        let mut replay = self.replay.lock();
+
+        // Frames sent after a recorded reconnect belong to a later connection
+        let boundary = replay
+            .records
+            .iter()
+            .skip(1)
+            .position(|record| record.opcode() == Some(OpCode::Handshake))
+            .map_or(replay.records.len(), |index| index + 1);
+
+        let matching = replay.records.iter().take(boundary).position(|record| {
+            record.direction == Direction::Sent
+                && record.opcode() == Some(message.opcode)
+                && record.payload["cmd"] == payload["cmd"]
+        });
+
+        match matching.and_then(|index| replay.records.remove(index)) {
+            Some(record) => {
+                if let (Some(old), Some(new)) =
+                    (record.payload["nonce"].as_str(), payload["nonce"].as_str())
+                {
+                    replay.nonces.insert(old.to_owned(), new.to_owned());
+                }
+            }
+            None if message.opcode == OpCode::Ping => replay.pongs.push_back(Message {
+                opcode: OpCode::Pong,
+                payload: message.payload.clone(),
+            }),
+            None => trace!("Frame not in the recording: {:?}", message),
+        }
+
+        if message.opcode == OpCode::Handshake {
+            self.handshake_sent = true;
+        }
+    }
+
+    /// The next frame for the client, if the recording has one ready.
+    fn next_received(&mut self) -> io::Result<Option<Message>> {
+        let mut replay = self.replay.lock();
+        if let Some(pong) = replay.pongs.pop_front() {
+            return Ok(Some(pong));
+        }
+
+        loop {
+            let front = match replay.records.front() {
+                Some(record) => (record.direction, record.opcode()),
+                None => return Ok(None),
+            };
+
+            match front {
+                // Heartbeats of the recorded session need no answer now
+                (Direction::Sent, Some(OpCode::Ping)) => {
+                    replay.records.pop_front();
+                }
+                (Direction::Sent, Some(OpCode::Handshake)) if self.handshake_sent => {
+                    return Err(io::ErrorKind::ConnectionReset.into());
+                }
+                (Direction::Sent, _) => return Ok(None),
+                (Direction::Received, _) => break,
+            }
+        }
+
+        let mut record = match replay.records.pop_front() {
+            Some(record) => record,
+            None => return Ok(None),
+        };
+        let nonce = record.payload["nonce"]
+            .as_str()
+            .and_then(|nonce| replay.nonces.get(nonce))
+            .cloned();
+        if let Some(nonce) = nonce {
+            record.payload["nonce"] = JsonValue::String(nonce);
+        }
+
+        record
+            .to_message()
+            .map(Some)
+            .map_err(|why| io::Error::new(io::ErrorKind::InvalidData, why))
+    }
+}
+
+impl Read for ReplayStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pending.is_empty() {
+            match self.next_received() {
+                Ok(Some(message)) => {
+                    self.pending = message
+                        .encode()
+                        .map_err(|why| io::Error::new(io::ErrorKind::InvalidData, why))?;
+                }
+                Ok(None) => return Err(io::ErrorKind::WouldBlock.into()),
+                // The recorded connection ended here
+                Err(why) if why.kind() == io::ErrorKind::ConnectionReset => return Ok(0),
+                Err(why) => return Err(why),
+            }
+        }
+
+        let len = buf.len().min(self.pending.len());
+        buf[..len].copy_from_slice(&self.pending[..len]);
+        self.pending.drain(..len);
+
+        Ok(len)
+    }
+}
+
+impl Write for ReplayStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.decoder.extend(buf);
+        loop {
+            match self.decoder.next_frame() {
+                Ok(Some(message)) => self.sent(message),
+                Ok(None) => break,
+                Err(why) => return Err(io::Error::new(io::ErrorKind::InvalidData, why)),
+            }
+        }
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ClientBuilder;
+    use serde_json::json;
+    use std::time::Duration;
+
+    /// A writer whose output can be inspected after handing it to a `Recorder`
+    #[derive(Clone, Default)]
+    struct Shared(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn record(direction: Direction, opcode: OpCode, payload: JsonValue) -> String {
+        let message = Message::new(opcode, payload).unwrap();
+        serde_json::to_string(&Record::new(direction, &message)).unwrap()
+    }
+
+    #[test]
+    fn records_frames_as_json_lines() {
+        let out = Shared::default();
+        let recorder = Recorder::new(out.clone());
+
+        let message = Message::new(OpCode::Frame, json!({ "cmd": "SET_ACTIVITY" })).unwrap();
+        recorder.record(Direction::Sent, &message);
+        recorder.record(Direction::Received, &message);
+
+        let text = String::from_utf8(out.0.lock().clone()).unwrap();
+        let lines: Vec<JsonValue> = text
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["direction"], "sent");
+        assert_eq!(lines[1]["direction"], "received");
+        assert_eq!(lines[0]["opcode"], 1);
+        assert_eq!(lines[0]["payload"], json!({ "cmd": "SET_ACTIVITY" }));
+        assert!(lines[0]["timestamp"].as_u64().unwrap() > 0);
+    }
+
+    #[test]
+    fn redacts_oauth2_credentials() {
+        let redacted = |payload| {
+            let line: JsonValue =
+                serde_json::from_str(&record(Direction::Sent, OpCode::Frame, payload)).unwrap();
+            line["payload"].clone()
+        };
+
+        assert_eq!(
+            redacted(json!({ "cmd": "AUTHENTICATE", "args": { "access_token": "secret" } })),
+            json!({ "cmd": "AUTHENTICATE", "args": { "access_token": REDACTED } })
+        );
+        assert_eq!(
+            redacted(json!({ "cmd": "AUTHORIZE", "data": { "code": "secret" }, "nonce": "1" })),
+            json!({ "cmd": "AUTHORIZE", "data": { "code": REDACTED }, "nonce": "1" })
+        );
+        assert_eq!(
+            redacted(json!({ "cmd": "AUTHENTICATE", "data": { "access_token": "secret" } })),
+            json!({ "cmd": "AUTHENTICATE", "data": { "access_token": REDACTED } })
+        );
+
+        let error = json!({ "cmd": "AUTHORIZE", "evt": "ERROR", "data": { "code": 5000 } });
+        assert_eq!(redacted(error.clone()), error);
+    }
+
+    #[test]
+    fn replays_a_recorded_session() {
+        let recording = [
+            record(
+                Direction::Sent,
+                OpCode::Handshake,
+                json!({ "v": 1, "client_id": "1" }),
+            ),
+            record(
+                Direction::Received,
+                OpCode::Frame,
+                json!({ "cmd": "DISPATCH", "evt": "READY", "data": { "v": 1 } }),
+            ),
+            record(
+                Direction::Sent,
+                OpCode::Frame,
+                json!({ "cmd": "SET_ACTIVITY", "args": {}, "nonce": "recorded" }),
+            ),
+            record(
+                Direction::Received,
+                OpCode::Frame,
+                json!({ "cmd": "SET_ACTIVITY", "data": { "state": "replayed" }, "nonce": "recorded" }),
+            ),
+            record(
+                Direction::Received,
+                OpCode::Frame,
+                json!({ "cmd": "DISPATCH", "evt": "ACTIVITY_JOIN", "data": { "secret": "s" } }),
+            ),
+        ]
+        .join("\n");
+
+        let connector = ReplayConnector::from_reader(recording.as_bytes()).unwrap();
+        let mut client = ClientBuilder::new(1)
+            .connector(connector)
+            .heartbeat_interval(None)
+            .build();
+
+        let (tx, rx) = crossbeam_channel::unbounded();
+        client.on_activity_join(move |ctx| tx.send(ctx.raw).unwrap());
+        client.start();
+        for _ in 0..500 {
+            if client.is_ready() {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+
+        let applied = client
+            .set_activity(|a| a.state(Some("replayed".to_owned())))
+            .unwrap();
+        assert_eq!(applied.data.unwrap().state.as_deref(), Some("replayed"));
+        assert_eq!(
+            rx.recv_timeout(Duration::from_secs(2)).unwrap(),
+            json!({ "secret": "s" })
+        );
+
+        client.shutdown();
+    }
+}
@@ -1,27 +1,21 @@
-use super::base::Connection;
 use crate::Result;
 use named_pipe::PipeClient;
-use std::{path::PathBuf, time};
+use std::{
+    path::{Path, PathBuf},
+    time,
+};
 
-pub struct WindowsConnection {
-    socket: PipeClient,
-}
-
-impl Connection for WindowsConnection {
-    type Socket = PipeClient;
+/// Connect to the named pipe at `path`.
+pub fn connect(path: &Path) -> Result<PipeClient> {
+    let mut socket = PipeClient::connect(path)?;
+    socket.set_write_timeout(Some(time::Duration::from_secs(1)));
+    // Pipes have no non-blocking mode, a short read timeout lets the manager poll
+    socket.set_read_timeout(Some(time::Duration::from_millis(50)));
 
-    fn connect() -> Result<Self> {
-        let connection_name = Self::socket_path(0);
-        let mut socket = PipeClient::connect(connection_name)?;
-        socket.set_write_timeout(Some(time::Duration::from_secs(1)));
-        Ok(Self { socket })
-    }
-
-    fn ipc_path() -> PathBuf {
-        PathBuf::from(r"\\.\pipe\")
-    }
+    Ok(socket)
+}
 
-    fn socket(&mut self) -> &mut Self::Socket {
-        &mut self.socket
-    }
+/// The default directories where pipes may be located.
+pub fn ipc_dirs() -> Vec<PathBuf> {
+    vec![PathBuf::from(r"\\.\pipe\")]
 }
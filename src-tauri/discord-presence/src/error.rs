@@ -6,7 +6,7 @@ use std::{
 };
 use thiserror::Error as AsError;
 
-use crate::models::Message;
+use crate::models::{Message, ValidationError};
 
 /// Error types from Discord
 #[derive(Debug, AsError)]
@@ -41,16 +41,185 @@ pub enum DiscordError {
     /// Connection Closing error
     #[error("Connection was closed prematurely")]
     ConnectionClosed,
+    /// Discord closed the connection and told us why
+    #[error("Discord closed the connection with {code:?}: {message}")]
+    Closed {
+        /// The close code
+        code: CloseCode,
+        /// Discord's description of why it closed the connection
+        message: String,
+    },
+    /// Discord stopped answering the heartbeat
+    #[error("Discord did not answer the heartbeat in time")]
+    HeartbeatTimeout,
+    /// Discord accepted the connection but did not answer the handshake
+    #[error("Discord did not answer the handshake in time")]
+    HandshakeTimeout,
+    /// An option set on the `ClientBuilder` is not supported by the async client
+    #[error("`ClientBuilder::{0}` only applies to the blocking Client")]
+    BlockingClientOnly(&'static str),
+    /// Frame exceeds the configured maximum size
+    #[error("Frame of {len} bytes exceeds the maximum of {max} bytes")]
+    FrameTooLarge {
+        /// Length announced in the frame header
+        len: usize,
+        /// The configured maximum
+        max: usize,
+    },
+    /// The callback exchanging an authorization code for an access token failed
+    #[error("Failed to exchange the authorization code: {0}")]
+    TokenExchange(Box<dyn std::error::Error + Send + Sync>),
+    /// The activity breaks Discord's limits, see [`ValidationMode::Reject`](crate::models::ValidationMode::Reject)
+    #[error(
+        "Invalid activity: {}",
+        .0.iter().map(ToString::to_string).collect::<Vec<_>>().join(", ")
+    )]
+    InvalidActivity(Vec<ValidationError>),
     /// Connection has not been started
     #[error("Connection has not been started")]
     NotStarted,
+    /// Discord rejected a command
+    #[error("Discord returned error {code:?}: {message}")]
+    Rpc {
+        /// The RPC error code
+        code: ErrorCode,
+        /// Discord's description of the error
+        message: String,
+    },
+}
+
+/// Error codes Discord attaches to rejected commands
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ErrorCode {
+    /// An unknown error occurred
+    Unknown,
+    /// The payload was invalid
+    InvalidPayload,
+    /// The command name was invalid
+    InvalidCommand,
+    /// The guild ID was invalid
+    InvalidGuild,
+    /// The event name was invalid
+    InvalidEvent,
+    /// The channel ID was invalid
+    InvalidChannel,
+    /// The client lacks the permissions for this command
+    InvalidPermissions,
+    /// The OAuth2 client ID was invalid
+    InvalidClientId,
+    /// The OAuth2 origin was invalid
+    InvalidOrigin,
+    /// The OAuth2 token was invalid
+    InvalidToken,
+    /// The user ID was invalid
+    InvalidUser,
+    /// A standard OAuth2 error occurred
+    OAuth2Error,
+    /// Selecting a channel timed out
+    SelectChannelTimedOut,
+    /// Fetching a guild timed out
+    GetGuildTimedOut,
+    /// The user is already in a voice channel
+    SelectVoiceForceRequired,
+    /// A shortcut is already being captured
+    CaptureShortcutAlreadyListening,
+    /// A code this crate does not know about
+    Other(u32),
+}
+
+impl From<u32> for ErrorCode {
+    fn from(code: u32) -> Self {
+        match code {
+            1000 => Self::Unknown,
+            4000 => Self::InvalidPayload,
+            4002 => Self::InvalidCommand,
+            4003 => Self::InvalidGuild,
+            4004 => Self::InvalidEvent,
+            4005 => Self::InvalidChannel,
+            4006 => Self::InvalidPermissions,
+            4007 => Self::InvalidClientId,
+            4008 => Self::InvalidOrigin,
+            4009 => Self::InvalidToken,
+            4010 => Self::InvalidUser,
+            5000 => Self::OAuth2Error,
+            5001 => Self::SelectChannelTimedOut,
+            5002 => Self::GetGuildTimedOut,
+            5003 => Self::SelectVoiceForceRequired,
+            5004 => Self::CaptureShortcutAlreadyListening,
+            other => Self::Other(other),
+        }
+    }
+}
+
+/// Codes Discord sends in a `Close` frame when it drops the connection
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CloseCode {
+    /// The connection was closed normally
+    Normal,
+    /// Discord does not support the request
+    Unsupported,
+    /// The connection was closed abnormally
+    Abnormal,
+    /// The client ID sent in the handshake was invalid
+    InvalidClientId,
+    /// The origin of the connection was invalid
+    InvalidOrigin,
+    /// Too many requests were sent
+    RateLimited,
+    /// The OAuth2 token was revoked
+    TokenRevoked,
+    /// The RPC version sent in the handshake is not supported
+    InvalidVersion,
+    /// The payload encoding is not supported
+    InvalidEncoding,
+    /// A code this crate does not know about
+    Other(u32),
+}
+
+impl From<u32> for CloseCode {
+    fn from(code: u32) -> Self {
+        match code {
+            1000 => Self::Normal,
+            1003 => Self::Unsupported,
+            1006 => Self::Abnormal,
+            4000 => Self::InvalidClientId,
+            4001 => Self::InvalidOrigin,
+            4002 => Self::RateLimited,
+            4003 => Self::TokenRevoked,
+            4004 => Self::InvalidVersion,
+            4005 => Self::InvalidEncoding,
+            other => Self::Other(other),
+        }
+    }
+}
+
+impl From<CloseCode> for u32 {
+    fn from(code: CloseCode) -> Self {
+        match code {
+            CloseCode::Normal => 1000,
+            CloseCode::Unsupported => 1003,
+            CloseCode::Abnormal => 1006,
+            CloseCode::InvalidClientId => 4000,
+            CloseCode::InvalidOrigin => 4001,
+            CloseCode::RateLimited => 4002,
+            CloseCode::TokenRevoked => 4003,
+            CloseCode::InvalidVersion => 4004,
+            CloseCode::InvalidEncoding => 4005,
+            CloseCode::Other(other) => other,
+        }
+    }
 }
 
 impl DiscordError {
     /// Tell whether an [`IoError`] would block the connection
+    ///
+    /// Read timeouts count as well, as pipes without a non-blocking mode report them instead.
     pub fn io_would_block(&self) -> bool {
         match self {
-            Self::IoError(ref err) => err.kind() == std::io::ErrorKind::WouldBlock,
+            Self::IoError(ref err) => matches!(
+                err.kind(),
+                std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut
+            ),
             _ => false,
         }
     }
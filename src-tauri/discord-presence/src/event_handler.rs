@@ -1,57 +1,287 @@
 use crate::{models::Event, Result};
-use parking_lot::RwLock;
-use serde_json::Value as JsonValue;
-use std::{collections::HashMap, sync::Arc};
+use crossbeam_channel::{unbounded, Sender};
+use parking_lot::{Mutex, RwLock};
+use serde::de::DeserializeOwned;
+use serde_json::{json, Value as JsonValue};
+use std::{
+    any::Any,
+    collections::HashMap,
+    fmt::Debug,
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    thread,
+};
 
-type Handler<'a> = Box<dyn Fn(Context) + 'a + Send + Sync>;
+type Handler = Arc<dyn Fn(Context) + Send + Sync>;
 
-type HandlerList<'a> = Vec<Handler<'a>>;
+type HandlerList = Vec<Entry>;
 
+/// The handlers to call for one event, bundled up for a [`HandlerExecutor`]
+pub type Job = Box<dyn FnOnce() + Send>;
+
+/// Runs event handlers, keeping slow ones from stalling the connection to Discord
+///
+/// Each handler runs inside [`catch_unwind`](std::panic::catch_unwind) whatever the
+/// executor, so a panicking handler is reported through `on_error` instead of taking the
+/// connection down.
+pub trait HandlerExecutor: Debug + Send + Sync {
+    /// Run `job`, which calls the handlers for one event
+    ///
+    /// Events are handed over in the order they arrive; run the jobs in that order to keep it.
+    fn execute(&self, job: Job);
+}
+
+/// Runs the handlers one event at a time on a dedicated thread, the default
+///
+/// The thread is started when the first event arrives and exits once the client is dropped.
+#[derive(Debug, Default)]
+pub struct ThreadExecutor {
+    jobs: Mutex<Option<Sender<Job>>>,
+}
+
+impl HandlerExecutor for ThreadExecutor {
+    fn execute(&self, job: Job) {
+        let mut jobs = self.jobs.lock();
+        if jobs.is_none() {
+            let (tx, rx) = unbounded::<Job>();
+            let spawned = thread::Builder::new()
+                .name("discord-presence-handlers".to_owned())
+                .spawn(move || rx.into_iter().for_each(|job| job()));
+
+            match spawned {
+                Ok(_) => *jobs = Some(tx),
+                Err(why) => error!("Failed to start the handler thread: {}", why),
+            }
+        }
+
+        match jobs.as_ref() {
+            Some(jobs) => {
+                if let Err(why) = jobs.send(job) {
+                    why.into_inner()();
+                }
+            }
+            None => job(),
+        }
+    }
+}
+
+/// Runs the handlers right away on the thread that received the event
+///
+/// Handlers must then return quickly, as nothing is read from Discord while they run.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct InlineExecutor;
+
+impl HandlerExecutor for InlineExecutor {
+    fn execute(&self, job: Job) {
+        job()
+    }
+}
+
+/// Identifies a registered handler, to remove it again with [`Client::off`](crate::Client::off)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct HandlerId(u64);
+
+#[derive(Clone)]
+struct Entry {
+    id: HandlerId,
+    /// Removed after its first call
+    once: bool,
+    handler: Handler,
+}
+
+/// The data an event was fired with
+///
+/// Handlers registered with [`Client::on_event`](crate::Client::on_event) get the data as
+/// JSON, the `on_*` shorthands get it parsed into the event's model, e.g.
+/// [`ReadyEvent`](crate::models::ReadyEvent) for [`Event::Ready`].
 #[derive(Debug, Clone)]
-pub struct Context {
-    // TODO: implement event data structures
-    pub event: JsonValue,
+pub struct Context<T = JsonValue> {
+    /// The event data
+    pub event: T,
+    /// The event data exactly as Discord sent it, including fields `T` does not cover
+    pub raw: JsonValue,
 }
 
 impl Context {
-    pub fn new(event: JsonValue) -> Self {
-        Self { event }
+    pub(crate) fn new(event: JsonValue) -> Self {
+        Self {
+            raw: event.clone(),
+            event,
+        }
+    }
+
+    /// Parse the event data into `T`, keeping the raw JSON
+    pub fn parse<T>(self) -> Result<Context<T>>
+    where
+        T: DeserializeOwned,
+    {
+        Ok(Context {
+            event: serde_json::from_value(self.raw.clone())?,
+            raw: self.raw,
+        })
     }
 }
 
 #[derive(Clone)]
-pub struct HandlerRegistry<'a> {
-    handlers: Arc<RwLock<HashMap<Event, HandlerList<'a>>>>,
+pub struct HandlerRegistry {
+    handlers: Arc<RwLock<HashMap<Event, HandlerList>>>,
+    unhandled: Arc<RwLock<HandlerList>>,
+    next_id: Arc<AtomicU64>,
+    executor: Arc<dyn HandlerExecutor>,
 }
 
-impl<'a> HandlerRegistry<'a> {
-    pub fn new() -> Self {
+impl HandlerRegistry {
+    /// Create a registry whose handlers are run by `executor`
+    pub fn new(executor: Arc<dyn HandlerExecutor>) -> Self {
         Self {
             handlers: Arc::new(RwLock::new(HashMap::new())),
+            unhandled: Arc::new(RwLock::new(Vec::new())),
+            next_id: Arc::new(AtomicU64::new(0)),
+            executor,
         }
     }
 
-    pub fn register<F>(&mut self, event: Event, handler: F)
+    pub fn register<F>(&mut self, event: Event, handler: F) -> HandlerId
     where
-        F: Fn(Context) + 'a + Send + Sync,
+        F: Fn(Context) + 'static + Send + Sync,
     {
-        let mut event_handlers = self.handlers.write();
-        let event_handler = event_handlers.entry(event).or_default();
-        event_handler.push(Box::new(handler));
+        let entry = self.entry(false, Arc::new(handler));
+        let id = entry.id;
+        self.handlers.write().entry(event).or_default().push(entry);
+        id
     }
 
-    pub fn handle(&mut self, event: Event, data: JsonValue) -> Result<()> {
-        let handlers = self.handlers.read();
-        if let Some(handlers) = handlers.get(&event) {
-            let context = Context::new(data);
+    /// Register a handler that is removed once it has been called
+    pub fn register_once<F>(&mut self, event: Event, handler: F) -> HandlerId
+    where
+        F: FnOnce(Context) + 'static + Send,
+    {
+        let handler = Mutex::new(Some(handler));
+        let entry = self.entry(
+            true,
+            Arc::new(move |context| {
+                if let Some(handler) = handler.lock().take() {
+                    handler(context)
+                }
+            }),
+        );
+        let id = entry.id;
+        self.handlers.write().entry(event).or_default().push(entry);
+        id
+    }
 
-            for handler in handlers {
-                handler(context.clone())
-            }
+    /// Remove the handler registered as `id`, returning whether it was still registered
+    pub fn remove(&self, id: HandlerId) -> bool {
+        let mut removed = false;
+        let mut retain = |list: &mut HandlerList| {
+            let len = list.len();
+            list.retain(|entry| entry.id != id);
+            removed |= list.len() != len;
+        };
+
+        self.handlers.write().values_mut().for_each(&mut retain);
+        retain(&mut self.unhandled.write());
+        removed
+    }
+
+    /// Hand `data` to the handlers registered for `event` through the executor
+    pub fn handle(&self, event: Event, data: JsonValue) -> Result<()> {
+        // Handlers may register or remove handlers themselves, so no lock is held while they run
+        let handlers = self
+            .handlers
+            .read()
+            .get(&event)
+            .cloned()
+            .unwrap_or_default();
+        if !handlers.is_empty() {
+            self.dispatch(Some(event), handlers, Context::new(data));
+        }
+
+        Ok(())
+    }
+
+    /// Register a handler for frames that do not map to a known [`Event`]
+    pub fn register_unhandled<F>(&mut self, handler: F) -> HandlerId
+    where
+        F: Fn(Context) + 'static + Send + Sync,
+    {
+        let entry = self.entry(false, Arc::new(handler));
+        let id = entry.id;
+        self.unhandled.write().push(entry);
+        id
+    }
+
+    /// Pass the raw payload of an unrecognized frame to the catch-all handlers
+    pub fn handle_unhandled(&self, payload: JsonValue) -> Result<()> {
+        let handlers = self.unhandled.read().clone();
+        if handlers.is_empty() {
+            trace!("No handler for frame: {}", payload);
+        } else {
+            self.dispatch(None, handlers, Context::new(payload));
         }
 
         Ok(())
     }
+
+    fn entry(&self, once: bool, handler: Handler) -> Entry {
+        Entry {
+            id: HandlerId(self.next_id.fetch_add(1, Ordering::Relaxed)),
+            once,
+            handler,
+        }
+    }
+
+    /// Call `handlers` on the executor; `event` is `None` for the catch-all handlers.
+    fn dispatch(&self, event: Option<Event>, handlers: HandlerList, context: Context) {
+        let registry = self.clone();
+        self.executor.execute(Box::new(move || {
+            for entry in handlers {
+                let result =
+                    panic::catch_unwind(AssertUnwindSafe(|| (entry.handler)(context.clone())));
+                if entry.once {
+                    registry.remove(entry.id);
+                }
+                if let Err(panic) = result {
+                    registry.report_panic(event, &*panic);
+                }
+            }
+        }));
+    }
+
+    /// Log a handler's panic and pass it to the [`Event::Error`] handlers.
+    fn report_panic(&self, event: Option<Event>, panic: &(dyn Any + Send)) {
+        let reason = panic
+            .downcast_ref::<&str>()
+            .copied()
+            .or_else(|| panic.downcast_ref::<String>().map(String::as_str))
+            .unwrap_or("unknown panic");
+        let message = match event {
+            Some(event) => format!("The {:?} handler panicked: {}", event, reason),
+            None => format!("A catch-all handler panicked: {}", reason),
+        };
+        error!("{}", message);
+
+        // A panicking error handler would otherwise be reported to itself forever
+        if event != Some(Event::Error) {
+            if let Err(why) = self.handle(Event::Error, json!({ "message": message })) {
+                trace!("discord error: {}", why);
+            }
+        }
+    }
+}
+
+/// Removes a handler when dropped, e.g. when a future waiting for an event is cancelled
+#[cfg(feature = "tokio")]
+pub(crate) struct RemoveOnDrop(pub HandlerRegistry, pub HandlerId);
+
+#[cfg(feature = "tokio")]
+impl Drop for RemoveOnDrop {
+    fn drop(&mut self) {
+        self.0.remove(self.1);
+    }
 }
 
 #[cfg(test)]
@@ -60,7 +290,7 @@ mod tests {
 
     #[test]
     fn can_register_event_handlers() {
-        let mut registry = HandlerRegistry::new();
+        let mut registry = HandlerRegistry::new(Arc::new(InlineExecutor));
         registry.register(Event::Ready, |_| unimplemented!());
         registry.register(Event::Ready, |_| unimplemented!());
         registry.register(Event::Error, |_| unimplemented!());
@@ -70,4 +300,90 @@ mod tests {
         assert_eq!(handlers[&Event::Ready].len(), 2);
         assert_eq!(handlers[&Event::Error].len(), 1);
     }
+
+    #[test]
+    fn unhandled_frames_reach_catch_all() {
+        let (tx, rx) = crossbeam_channel::unbounded();
+        let mut registry = HandlerRegistry::new(Arc::new(InlineExecutor));
+        registry.register(Event::Ready, |_| panic!("not a ready event"));
+        registry.register_unhandled(move |ctx| tx.send(ctx.event).unwrap());
+
+        registry
+            .handle_unhandled(serde_json::json!({ "evt": "GUILD_STATUS" }))
+            .unwrap();
+
+        assert_eq!(rx.try_recv().unwrap()["evt"], "GUILD_STATUS");
+    }
+
+    #[test]
+    fn removes_handlers_by_id() {
+        let mut registry = HandlerRegistry::new(Arc::new(InlineExecutor));
+        let ready = registry.register(Event::Ready, |_| panic!("removed"));
+        let unhandled = registry.register_unhandled(|_| panic!("removed"));
+        let error = registry.register(Event::Error, |_| {});
+
+        assert!(registry.remove(ready));
+        assert!(registry.remove(unhandled));
+        assert!(!registry.remove(ready));
+        assert_ne!(ready, error);
+
+        registry.handle(Event::Ready, JsonValue::Null).unwrap();
+        registry.handle_unhandled(JsonValue::Null).unwrap();
+        assert_eq!(registry.handlers.read()[&Event::Error].len(), 1);
+    }
+
+    #[test]
+    fn reports_panicking_handlers() {
+        let (tx, rx) = crossbeam_channel::unbounded();
+        let mut registry = HandlerRegistry::new(Arc::new(ThreadExecutor::default()));
+        registry.register(Event::Ready, |_| panic!("boom"));
+        registry.register(Event::Ready, {
+            let tx = tx.clone();
+            move |_| tx.send("still called".into()).unwrap()
+        });
+        registry.register(Event::Error, move |ctx| tx.send(ctx.event).unwrap());
+        registry.register(Event::Error, |_| panic!("not reported again"));
+
+        registry.handle(Event::Ready, JsonValue::Null).unwrap();
+
+        let timeout = std::time::Duration::from_secs(2);
+        assert_eq!(rx.recv_timeout(timeout).unwrap(), "still called");
+        assert_eq!(
+            rx.recv_timeout(timeout).unwrap(),
+            json!({ "message": "The Ready handler panicked: boom" })
+        );
+        assert!(rx
+            .recv_timeout(std::time::Duration::from_millis(100))
+            .is_err());
+    }
+
+    #[test]
+    fn once_handlers_remove_themselves() {
+        let (tx, rx) = crossbeam_channel::unbounded();
+        let mut registry = HandlerRegistry::new(Arc::new(InlineExecutor));
+        let id = registry.register_once(Event::Ready, move |ctx| tx.send(ctx.event).unwrap());
+
+        registry.handle(Event::Ready, 1.into()).unwrap();
+        registry.handle(Event::Ready, 2.into()).unwrap();
+
+        assert_eq!(rx.try_iter().collect::<Vec<_>>(), vec![JsonValue::from(1)]);
+        assert!(registry.handlers.read()[&Event::Ready].is_empty());
+        assert!(!registry.remove(id));
+    }
+
+    #[test]
+    fn parsed_context_keeps_raw_data() {
+        let raw = serde_json::json!({ "code": 4000, "message": "Invalid Client ID", "extra": 1 });
+        let ctx = Context::new(raw.clone())
+            .parse::<crate::models::ErrorEvent>()
+            .unwrap();
+
+        assert_eq!(ctx.event.code, Some(4000));
+        assert_eq!(ctx.event.message.as_deref(), Some("Invalid Client ID"));
+        assert_eq!(ctx.raw, raw);
+
+        assert!(Context::new(serde_json::json!("not an object"))
+            .parse::<crate::models::ErrorEvent>()
+            .is_err());
+    }
 }
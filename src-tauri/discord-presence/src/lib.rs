@@ -3,9 +3,6 @@
 
 //! A Rust library that allows the developer to interact with the Discord Presence API with ease
 
-pub(crate) static STARTED: AtomicBool = AtomicBool::new(false);
-pub(crate) static READY: AtomicBool = AtomicBool::new(false);
-
 // Cannot remove this *macro_use*, would break derive inside of macros
 #[macro_use]
 extern crate serde;
@@ -15,6 +12,10 @@ extern crate tracing;
 
 #[macro_use]
 mod macros;
+/// An async client for the Discord Presence API, running on tokio
+#[cfg(feature = "tokio")]
+pub mod async_client;
+mod builder;
 /// A client for the Discord Presence API
 pub mod client;
 mod connection;
@@ -23,10 +24,20 @@ pub mod error;
 mod event_handler;
 /// Models for discord activity
 pub mod models;
+/// A mock Discord server for tests
+#[cfg(all(unix, any(test, feature = "testing")))]
+pub mod testing;
 mod utils;
 
-use std::sync::atomic::AtomicBool;
-
+#[cfg(feature = "tokio")]
+pub use async_client::AsyncClient;
+pub use builder::ClientBuilder;
 pub use client::Client;
-pub use error::{DiscordError, Result};
+pub use connection::{
+    Connection, ConnectionInfo, Connector, Direction, DiscordBuild, Endpoint, IpcConnector,
+    RateLimit, ReconnectPolicy, Recorder, ReplayConnector, TcpConnector, DEFAULT_COMMAND_TIMEOUT,
+    DEFAULT_HEARTBEAT_INTERVAL, DEFAULT_HEARTBEAT_TIMEOUT, DEFAULT_MAX_FRAME_SIZE, IPC_PATH_ENV,
+};
+pub use error::{CloseCode, DiscordError, ErrorCode, Result};
+pub use event_handler::{Context, HandlerExecutor, HandlerId, InlineExecutor, Job, ThreadExecutor};
 pub use models::Event;
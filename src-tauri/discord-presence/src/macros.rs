@@ -134,3 +134,26 @@ macro_rules! builder {
         builder![@im ( $name $($body)* ) -> () ];
     }
 }
+
+macro_rules! event_handler_function {
+    ( $( $name:ident, $event:expr, $data:ident ),* ) => {
+        event_handler_function!{@gen $([ $name, $event, $data ])*}
+    };
+
+    (@gen $( [ $name:ident, $event:expr, $data:ident ] ), *) => {
+        $(
+            #[doc = concat!(
+                "Listens for the `", stringify!($event), "` event, with its data parsed into [`",
+                stringify!($data), "`]"
+            )]
+            pub fn $name<F>(&mut self, handler: F) -> crate::event_handler::HandlerId
+                where F: Fn(crate::event_handler::Context<$data>) + 'static + Send + Sync
+            {
+                self.on_event($event, move |ctx| match ctx.parse() {
+                    Ok(ctx) => handler(ctx),
+                    Err(why) => error!("Failed to parse the {:?} event: {}", $event, why),
+                })
+            }
+        )*
+    }
+}
@@ -8,3 +8,34 @@ builder! {SubscriptionArgs
 builder! {Subscription
     evt: String,
 }
+
+builder! {AuthorizeArgs
+    client_id: String,
+    scopes:    String vec,
+    rpc_token: String,
+    username:  String,
+}
+
+builder! {Authorization
+    code: String,
+}
+
+builder! {AuthenticateArgs
+    access_token: String,
+}
+
+builder! {Authentication
+    access_token: String,
+    user:         PartialUser,
+    scopes:       String vec,
+    expires:      String,
+    application:  Application,
+}
+
+builder! {Application
+    id:          String,
+    name:        String,
+    description: String,
+    icon:        String,
+    rpc_origins: String vec,
+}
@@ -9,6 +9,8 @@ pub mod payload;
 /// The rich presence module
 pub mod rich_presence;
 mod shared;
+/// Checks activities against Discord's limits
+pub mod validation;
 
 /// Different Discord commands
 #[derive(Debug, PartialEq, Eq, Copy, Clone, Deserialize, Serialize)]
@@ -18,6 +20,8 @@ pub enum Command {
     Dispatch,
     /// Authorize connection
     Authorize,
+    /// Authenticate with an OAuth2 access token
+    Authenticate,
     /// Subscribe to an event
     Subscribe,
     /// Unsubscribe from Discord
@@ -46,15 +50,30 @@ pub enum Event {
     ActivityJoinRequest,
 }
 
+impl Event {
+    /// Whether Discord only sends this event to clients that subscribed to it
+    pub fn requires_subscription(self) -> bool {
+        matches!(
+            self,
+            Event::ActivityJoin | Event::ActivitySpectate | Event::ActivityJoinRequest
+        )
+    }
+}
+
 pub use commands::*;
 pub use events::*;
 pub use message::{Message, OpCode};
+pub use shared::PartialUser;
+pub use validation::{ValidationError, ValidationMode};
 
 pub use rich_presence::*;
 
 /// Prelude for all Discord RPC types
 pub mod prelude {
-    pub use super::commands::{Subscription, SubscriptionArgs};
+    pub use super::commands::{
+        Application, AuthenticateArgs, Authentication, Authorization, AuthorizeArgs, Subscription,
+        SubscriptionArgs,
+    };
     pub use super::events::{ErrorEvent, ReadyEvent};
     pub use super::rich_presence::{
         ActivityJoinEvent, ActivityJoinRequestEvent, ActivitySpectateEvent,
@@ -1,6 +1,75 @@
 use super::shared::PartialUser;
 use crate::utils;
-use std::default::Default;
+use num_derive::FromPrimitive;
+use num_traits::FromPrimitive;
+use serde::{de::Error as _, Deserializer, Serializer};
+use std::{default::Default, time::Duration};
+
+/// Timestamps below this are in seconds rather than milliseconds, Discord accepts both
+const MILLISECONDS_FROM: u64 = 10_000_000_000;
+
+/// (De)serialize a fieldless enum as its integer discriminant, like Discord sends it
+macro_rules! serde_as_integer {
+    ( $name:ident ) => {
+        impl serde::Serialize for $name {
+            fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+            where
+                S: Serializer,
+            {
+                serializer.serialize_u8(*self as u8)
+            }
+        }
+
+        impl<'de> serde::Deserialize<'de> for $name {
+            fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+            where
+                D: Deserializer<'de>,
+            {
+                let value = <u8 as serde::Deserialize>::deserialize(deserializer)?;
+                Self::from_u8(value).ok_or_else(|| {
+                    D::Error::custom(format!(
+                        concat!("unknown ", stringify!($name), " {}"),
+                        value
+                    ))
+                })
+            }
+        }
+    };
+}
+
+/// What the user is doing, shown as the verb before the application's name
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, FromPrimitive)]
+pub enum ActivityType {
+    /// "Playing ..."
+    Playing = 0,
+    /// "Streaming ...", only shown for Twitch and YouTube URLs
+    Streaming = 1,
+    /// "Listening to ..."
+    Listening = 2,
+    /// "Watching ..."
+    Watching = 3,
+    /// A custom status
+    Custom = 4,
+    /// "Competing in ..."
+    Competing = 5,
+}
+
+serde_as_integer!(ActivityType);
+
+/// Which field of the activity is shown in the user's status in the member list
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, FromPrimitive)]
+pub enum StatusDisplayType {
+    /// The application's name
+    Name = 0,
+    /// The `state` field
+    State = 1,
+    /// The `details` field
+    Details = 2,
+}
+
+serde_as_integer!(StatusDisplayType);
 
 /// Args to set Discord activity
 #[derive(Debug, PartialEq, Eq, Deserialize, Serialize)]
@@ -64,9 +133,22 @@ builder! {ActivityJoinRequestEvent
     user: PartialUser,
 }
 
+impl ActivityJoinRequestEvent {
+    /// The ID of the user asking to join, to pass to
+    /// [`Client::accept_join_request`](crate::Client::accept_join_request) or
+    /// [`Client::reject_join_request`](crate::Client::reject_join_request)
+    pub fn user_id(&self) -> Option<u64> {
+        self.user.as_ref()?.id.as_ref()?.parse().ok()
+    }
+}
+
 builder! {Activity
+    activity_type: ActivityType alias = "type",
     state: String,
+    state_url: String,
     details: String,
+    details_url: String,
+    status_display_type: StatusDisplayType,
     instance: bool,
     timestamps: ActivityTimestamps func,
     assets: ActivityAssets func,
@@ -75,11 +157,54 @@ builder! {Activity
     secrets: ActivitySecrets func,
 }
 
+impl Activity {
+    /// Whether `other` shows the same as this activity, with timestamps differing by at most
+    /// `tolerance`
+    ///
+    /// Useful for activities that recompute their start time on every update, like the elapsed
+    /// time of a video.
+    pub fn matches(&self, other: &Activity, tolerance: Duration) -> bool {
+        let timestamps_match = match (&self.timestamps, &other.timestamps) {
+            (Some(ours), Some(theirs)) => ours.matches(theirs, tolerance),
+            (ours, theirs) => ours == theirs,
+        };
+        let without_timestamps = |activity: &Activity| Activity {
+            timestamps: None,
+            ..activity.clone()
+        };
+
+        timestamps_match && without_timestamps(self) == without_timestamps(other)
+    }
+}
+
 builder! {ActivityTimestamps
     start: u64,
     end: u64,
 }
 
+impl ActivityTimestamps {
+    /// Whether both timestamps differ from `other`'s by at most `tolerance`
+    pub fn matches(&self, other: &ActivityTimestamps, tolerance: Duration) -> bool {
+        let close = |ours: Option<u64>, theirs: Option<u64>| match (ours, theirs) {
+            (Some(ours), Some(theirs)) => {
+                u128::from(as_millis(ours).abs_diff(as_millis(theirs))) <= tolerance.as_millis()
+            }
+            (ours, theirs) => ours == theirs,
+        };
+
+        close(self.start, other.start) && close(self.end, other.end)
+    }
+}
+
+/// A Unix timestamp in milliseconds, whether it was given in seconds or milliseconds
+fn as_millis(timestamp: u64) -> u64 {
+    if timestamp < MILLISECONDS_FROM {
+        timestamp.saturating_mul(1000)
+    } else {
+        timestamp
+    }
+}
+
 builder! {ActivityAssets
     large_image: String,
     large_text: String,
@@ -87,9 +212,51 @@ builder! {ActivityAssets
     small_text: String,
 }
 
-builder! {ActivityButton
-    label: String,
-    url: String,
+/// ActivityButton struct
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Hash, Eq)]
+pub struct ActivityButton {
+    /// Optional label field
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    /// Optional url field
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+}
+
+impl ActivityButton {
+    /// Instantiates the `ActivityButton` struct using the `Default` implementation
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    builder_func![label, String];
+    builder_func![url, String];
+}
+
+impl<'de> serde::Deserialize<'de> for ActivityButton {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        // Discord only echoes the labels back when responding to `SET_ACTIVITY`
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Repr {
+            Label(String),
+            Full {
+                label: Option<String>,
+                url: Option<String>,
+            },
+        }
+
+        Ok(match Repr::deserialize(deserializer)? {
+            Repr::Label(label) => Self {
+                label: Some(label),
+                url: None,
+            },
+            Repr::Full { label, url } => Self { label, url },
+        })
+    }
 }
 
 builder! {ActivityParty
@@ -114,24 +281,93 @@ mod tests {
         let parsed_expected = serde_json::from_str::<Activity>(expected).unwrap();
 
         let activity = Activity::new()
-            .state("rusting")
-            .details("detailed")
+            .activity_type(Some(ActivityType::Listening))
+            .state(Some("rusting".to_owned()))
+            .state_url(Some("https://www.rust-lang.org".to_owned()))
+            .details(Some("detailed".to_owned()))
+            .details_url(Some("https://doc.rust-lang.org".to_owned()))
+            .status_display_type(Some(StatusDisplayType::Details))
             .instance(Some(true))
-            .timestamps(|t| t.start(1000).end(2000))
+            .timestamps(|t| t.start(Some(1000)).end(Some(2000)))
             .assets(|a| {
-                a.large_image("ferris")
-                    .large_text("Ferris")
-                    .small_image("rusting")
-                    .small_text("Rusting...")
+                a.large_image(Some("ferris".to_owned()))
+                    .large_text(Some("Ferris".to_owned()))
+                    .small_image(Some("rusting".to_owned()))
+                    .small_text(Some("Rusting...".to_owned()))
             })
-            .party(|p| p.id(Some(String::from("party"))).size((3, 6)))
+            .party(|p| p.id(Some(String::from("party"))).size(Some((3, 6))))
             .secrets(|s| {
-                s.join("025ed05c71f639de8bfaa0d679d7c94b2fdce12f")
-                    .spectate("e7eb30d2ee025ed05c71ea495f770b76454ee4e0")
-                    .game("4b2fdce12f639de8bfa7e3591b71a0d679d7c93f")
+                s.join(Some("025ed05c71f639de8bfaa0d679d7c94b2fdce12f".to_owned()))
+                    .spectate(Some("e7eb30d2ee025ed05c71ea495f770b76454ee4e0".to_owned()))
+                    .game(Some("4b2fdce12f639de8bfa7e3591b71a0d679d7c93f".to_owned()))
             });
 
         assert_eq!(parsed_expected, activity);
+        assert_eq!(
+            serde_json::to_value(&activity).unwrap(),
+            serde_json::from_str::<serde_json::Value>(expected).unwrap()
+        );
+    }
+
+    #[test]
+    fn activity_type_is_an_integer() {
+        let activity = Activity::new().activity_type(Some(ActivityType::Competing));
+        assert_eq!(serde_json::to_string(&activity).unwrap(), r#"{"type":5}"#);
+
+        let watching: Activity = serde_json::from_str(r#"{"type":3}"#).unwrap();
+        assert_eq!(watching.activity_type, Some(ActivityType::Watching));
+        assert!(serde_json::from_str::<Activity>(r#"{"type":42}"#).is_err());
+    }
+
+    #[test]
+    fn can_deserialize_echoed_buttons() {
+        let activity: Activity =
+            serde_json::from_str(r#"{"buttons":["Watch",{"label":"Join","url":"https://x.y"}]}"#)
+                .unwrap();
+
+        assert_eq!(
+            activity.buttons,
+            Some(vec![
+                ActivityButton::new().label(Some("Watch".to_owned())),
+                ActivityButton::new()
+                    .label(Some("Join".to_owned()))
+                    .url(Some("https://x.y".to_owned())),
+            ])
+        );
+    }
+
+    #[test]
+    fn matches_activities_with_drifting_timestamps() {
+        let playing = |start| {
+            Activity::new()
+                .state(Some("Watching".to_owned()))
+                .timestamps(|t| t.start(Some(start)))
+        };
+        let tolerance = Duration::from_secs(2);
+
+        assert!(playing(1_700_000_000_000).matches(&playing(1_700_000_001_500), tolerance));
+        assert!(!playing(1_700_000_000_000).matches(&playing(1_700_000_003_000), tolerance));
+        assert!(playing(1_700_000_000).matches(&playing(1_700_000_001_000), tolerance));
+        assert!(playing(1_700_000_000_000).matches(&playing(1_700_000_000_000), Duration::ZERO));
+        assert!(!playing(1_700_000_000_000).matches(
+            &playing(1_700_000_000_000).details(Some("x".to_owned())),
+            tolerance
+        ));
+        assert!(!playing(1_700_000_000_000).matches(
+            &playing(1_700_000_000_000)
+                .timestamps(|t| t.start(Some(1_700_000_000_000)).end(Some(1))),
+            tolerance
+        ));
+    }
+
+    #[test]
+    fn join_request_carries_user_id() {
+        let request: ActivityJoinRequestEvent =
+            serde_json::from_str(r#"{"user":{"id":"53908232506183680","username":"Mason"}}"#)
+                .unwrap();
+
+        assert_eq!(request.user_id(), Some(53908232506183680));
+        assert_eq!(ActivityJoinRequestEvent::new().user_id(), None);
     }
 
     #[test]
@@ -0,0 +1,445 @@
+use super::rich_presence::{Activity, ActivityButton};
+use crate::{DiscordError, Result};
+
+/// Fewest characters Discord accepts in a text field
+const MIN_TEXT_LEN: usize = 2;
+/// Most characters Discord accepts in a text field
+const MAX_TEXT_LEN: usize = 128;
+/// Most characters Discord accepts in a URL
+const MAX_URL_LEN: usize = 256;
+/// Most buttons Discord shows
+const MAX_BUTTONS: usize = 2;
+/// Most characters Discord accepts in a button label
+const MAX_LABEL_LEN: usize = 32;
+/// Appended to text shortened by [`Activity::sanitize`]
+const ELLIPSIS: char = '…';
+
+/// A way an [`Activity`] breaks Discord's limits
+///
+/// Discord drops such activities without telling the client why.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum ValidationError {
+    /// A text field has more characters than Discord allows
+    #[error("`{field}` has {len} characters, at most {max} are allowed")]
+    TooLong {
+        /// The offending field, e.g. `assets.large_text`
+        field: &'static str,
+        /// How many characters it has
+        len: usize,
+        /// How many it may have
+        max: usize,
+    },
+    /// A text field has fewer characters than Discord requires
+    #[error("`{field}` has {len} characters, at least {min} are required")]
+    TooShort {
+        /// The offending field
+        field: &'static str,
+        /// How many characters it has
+        len: usize,
+        /// How many it needs
+        min: usize,
+    },
+    /// A URL field does not hold an HTTP(S) URL
+    #[error("`{field}` is not an HTTP(S) URL: {url}")]
+    InvalidUrl {
+        /// The offending field
+        field: &'static str,
+        /// The URL it holds
+        url: String,
+    },
+    /// More buttons than Discord shows
+    #[error("{count} buttons, at most {max} are allowed")]
+    TooManyButtons {
+        /// How many buttons the activity has
+        count: usize,
+        /// How many it may have
+        max: usize,
+    },
+    /// A button lacks its label or its URL
+    #[error("button {index} needs both a label and a URL")]
+    IncompleteButton {
+        /// The position of the button
+        index: usize,
+    },
+    /// A button label has more characters than Discord allows
+    #[error("the label of button {index} has {len} characters, at most {max} are allowed")]
+    ButtonLabelTooLong {
+        /// The position of the button
+        index: usize,
+        /// How many characters the label has
+        len: usize,
+        /// How many it may have
+        max: usize,
+    },
+    /// A button does not link to an HTTP(S) URL
+    #[error("button {index} does not link to an HTTP(S) URL: {url}")]
+    InvalidButtonUrl {
+        /// The position of the button
+        index: usize,
+        /// The URL it links to
+        url: String,
+    },
+    /// The party has more members than it has room for
+    #[error("the party has {current} members but room for only {max}")]
+    PartyOverfull {
+        /// The current party size
+        current: u32,
+        /// The maximum party size
+        max: u32,
+    },
+    /// The activity ends before it starts
+    #[error("the end timestamp {end} is before the start timestamp {start}")]
+    EndBeforeStart {
+        /// The start timestamp
+        start: u64,
+        /// The end timestamp
+        end: u64,
+    },
+}
+
+/// What [`Client::set_activity`](crate::Client::set_activity) does with activities that
+/// break Discord's limits
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ValidationMode {
+    /// Send activities as they are
+    #[default]
+    Off,
+    /// Fail with [`DiscordError::InvalidActivity`] instead of sending the activity
+    Reject,
+    /// Fix the activity with [`Activity::sanitize`] before sending it
+    Sanitize,
+}
+
+impl ValidationMode {
+    /// Prepare `activity` for sending according to this mode
+    pub(crate) fn apply(self, activity: Activity) -> Result<Activity> {
+        match self {
+            ValidationMode::Off => Ok(activity),
+            ValidationMode::Reject => match activity.validate() {
+                Ok(()) => Ok(activity),
+                Err(errors) => Err(DiscordError::InvalidActivity(errors)),
+            },
+            ValidationMode::Sanitize => Ok(activity.sanitize()),
+        }
+    }
+}
+
+impl Activity {
+    /// Check the activity against Discord's limits, listing every one it breaks
+    pub fn validate(&self) -> std::result::Result<(), Vec<ValidationError>> {
+        let mut errors = Vec::new();
+
+        for (field, text) in self.texts() {
+            if let Some(text) = text {
+                check_text(&mut errors, field, text);
+            }
+        }
+
+        for (field, url) in self.urls() {
+            match url {
+                Some(url) if !is_http_url(url) => errors.push(ValidationError::InvalidUrl {
+                    field,
+                    url: url.clone(),
+                }),
+                Some(url) => check_len(&mut errors, field, url, MAX_URL_LEN),
+                None => {}
+            }
+        }
+
+        let buttons = self.buttons.as_deref().unwrap_or_default();
+        if buttons.len() > MAX_BUTTONS {
+            errors.push(ValidationError::TooManyButtons {
+                count: buttons.len(),
+                max: MAX_BUTTONS,
+            });
+        }
+        for (index, button) in buttons.iter().enumerate() {
+            check_button(&mut errors, index, button);
+        }
+
+        if let Some((current, max)) = self.party.as_ref().and_then(|party| party.size) {
+            if current > max {
+                errors.push(ValidationError::PartyOverfull { current, max });
+            }
+        }
+
+        if let Some(timestamps) = &self.timestamps {
+            if let (Some(start), Some(end)) = (timestamps.start, timestamps.end) {
+                if end < start {
+                    errors.push(ValidationError::EndBeforeStart { start, end });
+                }
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Fix whatever [`validate`](Self::validate) would complain about
+    ///
+    /// Text that is too long is cut short with an ellipsis, text that is too short and
+    /// invalid URLs are removed, invalid buttons are dropped and only the first two are kept,
+    /// an overfull party is capped at its maximum and an end before the start is removed.
+    pub fn sanitize(mut self) -> Self {
+        for text in self.texts_mut() {
+            *text = text
+                .take()
+                .and_then(|text| sanitize_text(text, MAX_TEXT_LEN));
+        }
+
+        for url in self.urls_mut() {
+            *url = url
+                .take()
+                .filter(|url| is_http_url(url) && url.chars().count() <= MAX_URL_LEN);
+        }
+
+        self.buttons = self.buttons.take().map(|buttons| {
+            buttons
+                .into_iter()
+                .filter_map(sanitize_button)
+                .take(MAX_BUTTONS)
+                .collect()
+        });
+
+        if let Some(party) = &mut self.party {
+            if let Some((current, max)) = party.size {
+                party.size = Some((current.min(max), max));
+            }
+        }
+
+        if let Some(timestamps) = &mut self.timestamps {
+            if let (Some(start), Some(end)) = (timestamps.start, timestamps.end) {
+                if end < start {
+                    timestamps.end = None;
+                }
+            }
+        }
+
+        self
+    }
+
+    fn texts(&self) -> [(&'static str, &Option<String>); 4] {
+        let assets = self.assets.as_ref();
+        [
+            ("state", &self.state),
+            ("details", &self.details),
+            ("assets.large_text", assets.map_or(&None, |a| &a.large_text)),
+            ("assets.small_text", assets.map_or(&None, |a| &a.small_text)),
+        ]
+    }
+
+    fn texts_mut(&mut self) -> Vec<&mut Option<String>> {
+        let mut texts = vec![&mut self.state, &mut self.details];
+        if let Some(assets) = &mut self.assets {
+            texts.push(&mut assets.large_text);
+            texts.push(&mut assets.small_text);
+        }
+        texts
+    }
+
+    fn urls(&self) -> [(&'static str, &Option<String>); 2] {
+        [
+            ("state_url", &self.state_url),
+            ("details_url", &self.details_url),
+        ]
+    }
+
+    fn urls_mut(&mut self) -> [&mut Option<String>; 2] {
+        [&mut self.state_url, &mut self.details_url]
+    }
+}
+
+fn check_text(errors: &mut Vec<ValidationError>, field: &'static str, text: &str) {
+    let len = text.chars().count();
+    if len < MIN_TEXT_LEN {
+        errors.push(ValidationError::TooShort {
+            field,
+            len,
+            min: MIN_TEXT_LEN,
+        });
+    }
+    check_len(errors, field, text, MAX_TEXT_LEN);
+}
+
+fn check_len(errors: &mut Vec<ValidationError>, field: &'static str, text: &str, max: usize) {
+    let len = text.chars().count();
+    if len > max {
+        errors.push(ValidationError::TooLong { field, len, max });
+    }
+}
+
+fn check_button(errors: &mut Vec<ValidationError>, index: usize, button: &ActivityButton) {
+    let (label, url) = match (&button.label, &button.url) {
+        (Some(label), Some(url)) => (label, url),
+        _ => return errors.push(ValidationError::IncompleteButton { index }),
+    };
+
+    let len = label.chars().count();
+    if len > MAX_LABEL_LEN {
+        errors.push(ValidationError::ButtonLabelTooLong {
+            index,
+            len,
+            max: MAX_LABEL_LEN,
+        });
+    }
+
+    if !is_http_url(url) || url.chars().count() > MAX_URL_LEN {
+        errors.push(ValidationError::InvalidButtonUrl {
+            index,
+            url: url.clone(),
+        });
+    }
+}
+
+fn sanitize_button(button: ActivityButton) -> Option<ActivityButton> {
+    let url = button
+        .url
+        .filter(|url| is_http_url(url) && url.chars().count() <= MAX_URL_LEN)?;
+    // Labels have no minimum length, unlike the other text fields
+    let label = shorten(button.label?, MAX_LABEL_LEN);
+
+    Some(ActivityButton::new().label(Some(label)).url(Some(url)))
+}
+
+/// [`shorten`] `text` to `max` characters, or drop it if it is too short.
+fn sanitize_text(text: String, max: usize) -> Option<String> {
+    if text.chars().count() < MIN_TEXT_LEN {
+        return None;
+    }
+
+    Some(shorten(text, max))
+}
+
+/// Shorten `text` to `max` characters ending in an ellipsis.
+fn shorten(text: String, max: usize) -> String {
+    if text.chars().count() <= max {
+        return text;
+    }
+
+    let mut short: String = text.chars().take(max - 1).collect();
+    short.push(ELLIPSIS);
+    short
+}
+
+fn is_http_url(url: &str) -> bool {
+    ["http://", "https://"].iter().any(|scheme| {
+        url.len() > scheme.len()
+            && url
+                .get(..scheme.len())
+                .is_some_and(|prefix| prefix.eq_ignore_ascii_case(scheme))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn button(label: &str, url: &str) -> ActivityButton {
+        ActivityButton::new()
+            .label(Some(label.to_owned()))
+            .url(Some(url.to_owned()))
+    }
+
+    fn broken() -> Activity {
+        Activity::new()
+            .state(Some("x".repeat(130)))
+            .details(Some("x".to_owned()))
+            .details_url(Some("ftp://example.com".to_owned()))
+            .assets(|a| a.large_text(Some("Fine".to_owned())))
+            .buttons(Some(vec![
+                button("Watch", "javascript:alert(1)"),
+                button(&"y".repeat(40), "https://example.com"),
+                button("Listen", "https://example.com/listen"),
+                ActivityButton::new().label(Some("No URL".to_owned())),
+            ]))
+            .party(|p| p.size(Some((5, 4))))
+            .timestamps(|t| t.start(Some(2000)).end(Some(1000)))
+    }
+
+    #[test]
+    fn valid_activity_passes() {
+        let activity = Activity::new()
+            .state(Some("Browsing".to_owned()))
+            .buttons(Some(vec![button("Open", "https://premid.app")]))
+            .party(|p| p.size(Some((1, 4))))
+            .timestamps(|t| t.start(Some(1000)));
+
+        assert_eq!(activity.validate(), Ok(()));
+        assert_eq!(Activity::new().validate(), Ok(()));
+    }
+
+    #[test]
+    fn lists_every_broken_limit() {
+        assert_eq!(
+            broken().validate(),
+            Err(vec![
+                ValidationError::TooLong {
+                    field: "state",
+                    len: 130,
+                    max: 128,
+                },
+                ValidationError::TooShort {
+                    field: "details",
+                    len: 1,
+                    min: 2,
+                },
+                ValidationError::InvalidUrl {
+                    field: "details_url",
+                    url: "ftp://example.com".to_owned(),
+                },
+                ValidationError::TooManyButtons { count: 4, max: 2 },
+                ValidationError::InvalidButtonUrl {
+                    index: 0,
+                    url: "javascript:alert(1)".to_owned(),
+                },
+                ValidationError::ButtonLabelTooLong {
+                    index: 1,
+                    len: 40,
+                    max: 32,
+                },
+                ValidationError::IncompleteButton { index: 3 },
+                ValidationError::PartyOverfull { current: 5, max: 4 },
+                ValidationError::EndBeforeStart {
+                    start: 2000,
+                    end: 1000,
+                },
+            ])
+        );
+    }
+
+    #[test]
+    fn sanitizes_into_a_valid_activity() {
+        let activity = broken().sanitize();
+
+        assert_eq!(activity.validate(), Ok(()));
+        let state = activity.state.unwrap();
+        assert_eq!(state.chars().count(), 128);
+        assert!(state.ends_with(ELLIPSIS));
+        assert_eq!(activity.details, None);
+        assert_eq!(activity.details_url, None);
+        assert_eq!(activity.assets.unwrap().large_text.as_deref(), Some("Fine"));
+        assert_eq!(
+            activity.buttons.unwrap(),
+            vec![
+                button(
+                    &format!("{}{}", "y".repeat(31), ELLIPSIS),
+                    "https://example.com"
+                ),
+                button("Listen", "https://example.com/listen"),
+            ]
+        );
+        assert_eq!(activity.party.unwrap().size, Some((4, 4)));
+        assert_eq!(activity.timestamps.unwrap().end, None);
+    }
+
+    #[test]
+    fn validate_and_sanitize_agree_on_short_labels() {
+        let activity = Activity::new().buttons(Some(vec![button("A", "https://example.com")]));
+
+        assert_eq!(activity.validate(), Ok(()));
+        assert_eq!(activity.clone().sanitize(), activity);
+    }
+}
@@ -0,0 +1,308 @@
+//! A fake Discord to run the library against when Discord itself is not available
+//!
+//! ```no_run
+//! use discord_presence::testing::MockServer;
+//!
+//! let server = MockServer::start().unwrap();
+//! let mut client = server.client_builder(1).build();
+//! client.start();
+//! ```
+
+use crate::{
+    connection::{close_payload, FrameReader},
+    models::{Event, Message, OpCode},
+    utils, ClientBuilder, CloseCode,
+};
+use parking_lot::Mutex;
+use serde_json::{json, Value as JsonValue};
+use std::{
+    fs,
+    io::{self, Write},
+    os::unix::net::{UnixListener, UnixStream},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+/// How often the server checks whether it was dropped while waiting for data
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+#[derive(Default)]
+struct State {
+    ready: Mutex<JsonValue>,
+    commands: Mutex<Vec<JsonValue>>,
+    connections: Mutex<usize>,
+    stream: Mutex<Option<UnixStream>>,
+    silent: AtomicBool,
+    stop: AtomicBool,
+}
+
+/// A scriptable stand-in for Discord listening on `discord-ipc-0` in a temporary directory
+///
+/// It completes the handshake, answers every command, records what it received and can
+/// send events or close the connection on demand. Connections are served one at a time.
+pub struct MockServer {
+    dir: PathBuf,
+    state: Arc<State>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl MockServer {
+    /// Start a server answering the handshake with a minimal `READY` payload
+    pub fn start() -> io::Result<Self> {
+        Self::with_ready(json!({
+            "v": 1,
+            "config": {
+                "cdn_host": "cdn.discordapp.com",
+                "api_endpoint": "//discord.com/api",
+                "environment": "production"
+            },
+            "user": {
+                "id": "1",
+                "username": "mock",
+                "discriminator": "0",
+                "avatar": null
+            }
+        }))
+    }
+
+    /// Start a server answering the handshake with `ready` as the `READY` data
+    pub fn with_ready(ready: JsonValue) -> io::Result<Self> {
+        let dir = std::env::temp_dir().join(format!("discord-presence-{}", utils::nonce()));
+        fs::create_dir_all(&dir)?;
+
+        let listener = UnixListener::bind(dir.join("discord-ipc-0"))?;
+        listener.set_nonblocking(true)?;
+
+        let state = Arc::new(State::default());
+        *state.ready.lock() = ready;
+
+        let thread = thread::spawn({
+            let state = state.clone();
+            move || serve(listener, &state)
+        });
+
+        Ok(Self {
+            dir,
+            state,
+            thread: Some(thread),
+        })
+    }
+
+    /// The directory holding the server's socket
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// A [`ClientBuilder`] that looks for the server before any real Discord client
+    pub fn client_builder(&self, client_id: u64) -> ClientBuilder {
+        ClientBuilder::new(client_id)
+            .ipc_dir(&self.dir)
+            .preferred_socket(0)
+    }
+
+    /// Change the `READY` data sent on the next handshake
+    pub fn set_ready(&self, ready: JsonValue) {
+        *self.state.ready.lock() = ready;
+    }
+
+    /// Stop answering handshakes, like a Discord that hangs while starting up
+    ///
+    /// Connections are still accepted and read from until the client hangs up.
+    pub fn set_silent(&self, silent: bool) {
+        self.state.silent.store(silent, Ordering::Release);
+    }
+
+    /// How many handshakes the server has completed
+    pub fn connections(&self) -> usize {
+        *self.state.connections.lock()
+    }
+
+    /// Every command frame received so far, in order
+    pub fn commands(&self) -> Vec<JsonValue> {
+        self.state.commands.lock().clone()
+    }
+
+    /// The activities received through `SET_ACTIVITY`, `Null` for cleared activities
+    pub fn activities(&self) -> Vec<JsonValue> {
+        self.commands()
+            .into_iter()
+            .filter(|command| command["cmd"] == "SET_ACTIVITY")
+            .map(|command| command["args"]["activity"].clone())
+            .collect()
+    }
+
+    /// Wait until `condition` holds for the server, giving up after `timeout`
+    pub fn wait_until<F>(&self, timeout: Duration, condition: F) -> bool
+    where
+        F: Fn(&Self) -> bool,
+    {
+        let deadline = Instant::now() + timeout;
+        while !condition(self) {
+            if Instant::now() >= deadline {
+                return false;
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
+
+        true
+    }
+
+    /// Send `event` with `data` to the connected client
+    pub fn dispatch(&self, event: Event, data: JsonValue) -> io::Result<()> {
+        let payload = json!({ "cmd": "DISPATCH", "evt": event, "data": data });
+        self.send(&frame(OpCode::Frame, &payload)?)
+    }
+
+    /// Send a `Close` frame and drop the connection, like Discord does when it rejects a client
+    pub fn close(&self, code: CloseCode, message: &str) -> io::Result<()> {
+        self.send(&frame(OpCode::Close, &close_payload(code, message))?)?;
+
+        if let Some(stream) = self.state.stream.lock().take() {
+            stream.shutdown(std::net::Shutdown::Both).ok();
+        }
+
+        Ok(())
+    }
+
+    fn send(&self, message: &Message) -> io::Result<()> {
+        match self.state.stream.lock().as_mut() {
+            Some(stream) => write_message(stream, message),
+            None => Err(io::ErrorKind::NotConnected.into()),
+        }
+    }
+}
+
+impl Drop for MockServer {
+    fn drop(&mut self) {
+        self.state.stop.store(true, Ordering::Release);
+        if let Some(thread) = self.thread.take() {
+            thread.join().ok();
+        }
+        fs::remove_dir_all(&self.dir).ok();
+    }
+}
+
+fn serve(listener: UnixListener, state: &State) {
+    while !state.stop.load(Ordering::Acquire) {
+        match listener.accept() {
+            Ok((stream, _)) => {
+                if let Err(why) = handle_connection(stream, state) {
+                    trace!("Mock server connection ended: {}", why);
+                }
+                state.stream.lock().take();
+            }
+            Err(ref why) if why.kind() == io::ErrorKind::WouldBlock => thread::sleep(POLL_INTERVAL),
+            Err(why) => {
+                error!("Mock server failed to accept: {}", why);
+                break;
+            }
+        }
+    }
+}
+
+fn handle_connection(mut stream: UnixStream, state: &State) -> crate::Result<()> {
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(POLL_INTERVAL))?;
+    let mut reader = FrameReader::default();
+
+    let handshake = match next_message(&mut stream, &mut reader, state)? {
+        Some(message) if message.opcode == OpCode::Handshake => message,
+        _ => return Ok(()),
+    };
+    trace!("Mock server handshake: {}", handshake.payload);
+    if state.silent.load(Ordering::Acquire) {
+        while next_message(&mut stream, &mut reader, state)?.is_some() {}
+        return Ok(());
+    }
+
+    let ready = json!({ "cmd": "DISPATCH", "evt": "READY", "data": *state.ready.lock() });
+    write_message(&mut stream, &frame(OpCode::Frame, &ready)?)?;
+    *state.stream.lock() = Some(stream.try_clone()?);
+    *state.connections.lock() += 1;
+
+    while let Some(message) = next_message(&mut stream, &mut reader, state)? {
+        match message.opcode {
+            OpCode::Ping => reply(
+                state,
+                &mut stream,
+                &Message {
+                    opcode: OpCode::Pong,
+                    payload: message.payload,
+                },
+            )?,
+            OpCode::Frame => {
+                let command: JsonValue = serde_json::from_str(&message.payload)?;
+                let response = respond(&command, state);
+                state.commands.lock().push(command);
+                reply(state, &mut stream, &frame(OpCode::Frame, &response)?)?;
+            }
+            OpCode::Close => break,
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+/// The answer Discord would give to `command`
+fn respond(command: &JsonValue, state: &State) -> JsonValue {
+    let data = match command["cmd"].as_str() {
+        Some("AUTHORIZE") => json!({ "code": "mock-code" }),
+        Some("AUTHENTICATE") => json!({
+            "access_token": command["args"]["access_token"],
+            "user": state.ready.lock()["user"],
+            "scopes": [],
+            "application": { "id": "1", "name": "mock" }
+        }),
+        Some("SET_ACTIVITY") => command["args"]["activity"].clone(),
+        Some("SUBSCRIBE") | Some("UNSUBSCRIBE") => json!({ "evt": command["evt"] }),
+        _ => json!({}),
+    };
+
+    json!({
+        "cmd": command["cmd"],
+        "data": data,
+        "evt": null,
+        "nonce": command["nonce"]
+    })
+}
+
+/// Wait for the next frame, returning `None` once the server stops or the client hangs up
+fn next_message(
+    stream: &mut UnixStream,
+    reader: &mut FrameReader,
+    state: &State,
+) -> crate::Result<Option<Message>> {
+    while !state.stop.load(Ordering::Acquire) {
+        match reader.read_frame(stream) {
+            Ok(message) => return Ok(Some(message)),
+            Err(why) if why.io_would_block() => {}
+            Err(crate::DiscordError::ConnectionClosed) => return Ok(None),
+            Err(why) => return Err(why),
+        }
+    }
+
+    Ok(None)
+}
+
+/// Write on the connection's own handle while keeping injected frames from interleaving
+fn reply(state: &State, stream: &mut UnixStream, message: &Message) -> io::Result<()> {
+    let _shared = state.stream.lock();
+    write_message(stream, message)
+}
+
+fn frame(opcode: OpCode, payload: &JsonValue) -> io::Result<Message> {
+    Message::new(opcode, payload).map_err(|why| io::Error::new(io::ErrorKind::InvalidData, why))
+}
+
+fn write_message(stream: &mut UnixStream, message: &Message) -> io::Result<()> {
+    let bytes = message
+        .encode()
+        .map_err(|why| io::Error::new(io::ErrorKind::InvalidData, why))?;
+    stream.write_all(&bytes)
+}
@@ -7,3 +7,10 @@ pub fn pid() -> u32 {
 pub fn nonce() -> String {
     Uuid::new_v4().to_string()
 }
+
+/// A random number in `[0, 1)`
+pub fn random_fraction() -> f64 {
+    // The version and variant bits sit above the low 62 bits, which are all random;
+    // 53 of them fill an f64 mantissa exactly
+    (Uuid::new_v4().as_u128() as u64 & ((1 << 53) - 1)) as f64 / (1u64 << 53) as f64
+}
@@ -17,7 +17,7 @@ use notify::Watcher;
 
 use crossbeam_channel::unbounded;
 use discord_presence::{
-    models::{Activity, ActivityButton},
+    models::{Activity, ActivityButton, ActivityType, PartialUser, ValidationMode},
     Client,
 };
 
@@ -45,6 +45,8 @@ struct SetActivityData {
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 struct PresenceData {
+    #[serde(rename(deserialize = "type"))]
+    activity_type: Option<ActivityType>,
     details: Option<String>,
     state: Option<String>,
     #[serde(rename(deserialize = "largeImageText"))]
@@ -62,6 +64,16 @@ struct PresenceData {
     buttons: Option<Vec<ActivityButton>>,
 }
 
+impl User {
+    fn from_partial(user: PartialUser) -> Option<Self> {
+        Some(Self {
+            id: user.id?,
+            username: user.username?,
+            avatar: user.avatar,
+        })
+    }
+}
+
 #[derive(Serialize, Debug)]
 struct AppState {
     connected: bool,
@@ -85,13 +97,15 @@ fn get_user(handle: AppHandle) -> Result<User, ()> {
             let socket = socket.clone();
             if let Some(socket) = socket {
                 let mut client = Client::new(503557087041683458);
-                _ = client.start();
+                client.start();
 
                 client.on_ready({
                     let handle = handle.clone();
                     move |ctx| {
-                        let user =
-                            serde_json::from_value::<User>(ctx.event["user"].clone()).unwrap();
+                        let user = match ctx.event.user.and_then(User::from_partial) {
+                            Some(user) => user,
+                            None => return,
+                        };
 
                         let app_state = handle.state::<Arc<Mutex<Option<AppState>>>>();
                         let mut lock = app_state.lock().unwrap();
@@ -176,7 +190,6 @@ fn main() {
 
     std::thread::spawn({
         let handle = handle.clone();
-        let tx2 = tx2.clone();
 
         move || {
             let client = Arc::new(Mutex::new(None::<Client>));
@@ -230,8 +243,8 @@ fn main() {
                     let mut lock = client.lock().unwrap();
 
                     if lock.is_none() {
-                        let mut client = Client::new(activity.client_id.parse().unwrap());
-                        _ = client.start();
+                        let mut client = presence_client(activity.client_id.parse().unwrap());
+                        client.start();
 
                         *lock = Some(client);
                     } else if lock.as_ref().unwrap().client_id()
@@ -240,8 +253,8 @@ fn main() {
                         let client = lock.as_mut().unwrap();
                         client.clear();
 
-                        let mut client = Client::new(activity.client_id.parse().unwrap());
-                        _ = client.start();
+                        let mut client = presence_client(activity.client_id.parse().unwrap());
+                        client.start();
 
                         *lock = Some(client);
                     }
@@ -250,6 +263,7 @@ fn main() {
 
                     let data = activity.data.clone();
                     let activity_data = Activity::new()
+                        .activity_type(data.activity_type)
                         .details(data.details)
                         .state(data.state)
                         .timestamps(|t| t.start(data.start_timestamp).end(data.end_timestamp))
@@ -261,10 +275,8 @@ fn main() {
                         })
                         .buttons(data.buttons);
 
+                    // The client sets the activity once it is connected again
                     if let Err(_) = client.set_activity(|_| activity_data) {
-                        _ = client.start();
-                        tx2.send(activity).ok();
-
                         let state = handle.try_state::<Arc<Mutex<Option<AppState>>>>();
 
                         if let Some(state) = state {
@@ -350,6 +362,17 @@ fn main() {
     app.run(|_, _| {})
 }
 
+/// A client for presences sent by the extension, fixed up to fit Discord's limits
+///
+/// The extension resends the presence every few seconds with freshly computed timestamps,
+/// which only needs to reach Discord if something else changed.
+fn presence_client(client_id: u64) -> Client {
+    Client::builder(client_id)
+        .activity_validation(ValidationMode::Sanitize)
+        .skip_duplicate_activities(Some(std::time::Duration::from_secs(2)))
+        .build()
+}
+
 fn pick_folder() -> Result<String, ()> {
     if let Ok(response) = dialog::pick_folder(None::<&Path>) {
         return match response {